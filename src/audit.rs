@@ -0,0 +1,113 @@
+// This is responsible for checking that every resource in a game reads, decompresses and parses,
+// to verify a newly acquired dump is complete before archiving it.
+// Every copy of resources on multiple disks is checked, and they must match.
+
+use crate::map::{Entry, Map, ResourceType};
+use crate::picture;
+use crate::resource_files::Files;
+use crate::resource_reader;
+use crate::view;
+
+pub struct Check {
+    pub resource_type: ResourceType,
+    pub resource_number: usize,
+    pub source: String,
+    pub problems: Vec<String>, // Empty if it passed.
+}
+
+pub fn audit(map: &Map, files: &Files) -> Vec<Check> {
+    map.entries.iter().map(|entry| check(entry, files)).collect()
+}
+
+fn check(entry: &Entry, files: &Files) -> Check {
+    let mut problems: Vec<String> = Vec::new();
+    let copies = resource_reader::read_copies(entry, files);
+    for (location, result) in &copies {
+        let name = if entry.other_copies.is_empty() { "It".to_string() } else { format!("Copy in {}", location.name()) };
+        match result {
+            Err(error) => problems.push(format!("{} {}", name, error)),
+            Ok(data) => {
+                if let Err(error) = parse(entry.resource_type, data) {
+                    problems.push(format!("{} can't be parsed: {}", name, error));
+                }
+            }
+        }
+    }
+    for (first, location) in resource_reader::differing_copies(&copies) {
+        problems.push(format!("Copy in {} differs from {}", location.name(), first.name()));
+    }
+    Check { resource_type: entry.resource_type, resource_number: entry.resource_number, source: entry.source_name(), problems }
+}
+
+// Only the types this tool understands are parsed, the rest pass if they decompress.
+fn parse(resource_type: ResourceType, data: &[u8]) -> Result<(), String> {
+    match resource_type {
+        ResourceType::Picture => picture::Picture::parse(data).map(|_| ()),
+        ResourceType::View => view::View::parse(data).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+// A plain text table, with a row per resource.
+pub fn table(checks: &[Check]) -> String {
+    let rows: Vec<[String; 5]> = checks.iter().map(|c| [
+        format!("{:?}", c.resource_type),
+        c.resource_number.to_string(),
+        c.source.clone(),
+        if c.problems.is_empty() { "pass" } else { "FAIL" }.to_string(),
+        c.problems.join("; "),
+    ]).collect();
+    let headings = ["Type", "Number", "Source", "Result", "Reason"].map(|h| h.to_string());
+    let mut widths = headings.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&headings).chain(&rows) {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::SAMPLE_PATH;
+    use crate::resource_source::Memory;
+
+    #[test]
+    fn it_reports_corrupt_resources() {
+        let map_data = std::fs::read(format!("{}/resource.map", SAMPLE_PATH)).unwrap();
+        let mut volume = std::fs::read(format!("{}/resource.001", SAMPLE_PATH)).unwrap();
+        let map = Map::read(SAMPLE_PATH);
+        let view = map.entries.iter().position(|e| e.resource_type == ResourceType::View).unwrap();
+        let picture = map.entries.iter().position(|e| e.resource_type == ResourceType::Picture).unwrap();
+        volume[map.entries[view].offset + 6] = 9; // Compression method.
+        let picture_offset = map.entries[picture].offset;
+        let picture_size = volume[picture_offset + 2] as usize + ((volume[picture_offset + 3] as usize) << 8);
+        volume.truncate(picture_offset + picture_size); // Cut short.
+        let files = Files::new(Box::new(Memory { files: vec![("resource.map".to_string(), map_data), ("resource.001".to_string(), volume)] }));
+
+        let checks = audit(&map, &files);
+        let failed: Vec<usize> = (0..checks.len()).filter(|&i| !checks[i].problems.is_empty()).collect();
+        assert!(failed.contains(&view) && failed.contains(&picture));
+        assert_eq!(checks[view].problems, vec!["It has an unknown compression method: 9"]);
+        assert_eq!(checks[picture].problems, vec!["It is too short"]);
+        let table = table(&checks);
+        assert!(table.starts_with("Type  "));
+        assert_eq!(table.lines().count(), checks.len() + 1);
+    }
+
+    #[test]
+    fn it_reports_copies_that_differ() {
+        // Views with no loops, that differ in an unused byte.
+        let memory = Memory { files: resource_reader::tests::two_disks(vec![0; 8], vec![0, 0, 0, 0, 0, 0, 0, 1]) };
+        let map = Map::read_from(&memory);
+        let checks = audit(&map, &Files::new(Box::new(memory)));
+        assert_eq!(checks[0].problems, vec!["Copy in resource.002 differs from resource.001"]);
+    }
+}
@@ -18,13 +18,14 @@ impl<'a> BitStreamLSB<'a> {
         BitStreamLSB { data, index: 0, bit_buffer: 0, bits_in_buffer: 0 }
     }
 
-    pub fn next(&mut self, bits_wanted: usize) -> usize {
+    // None once the data runs out.
+    pub fn next(&mut self, bits_wanted: usize) -> Option<usize> {
         assert!(1 <= bits_wanted && bits_wanted <= 16);
 
         // Fetch more from data to top up the buffer.
         while bits_wanted > self.bits_in_buffer {
             // Grab another byte, add it to bit_buffer, shifted to the significant end.
-            let byte = self.data[self.index];
+            let byte = *self.data.get(self.index)?;
             self.index += 1;
             self.bit_buffer += (byte as usize) << self.bits_in_buffer;
             self.bits_in_buffer += 8;
@@ -34,7 +35,55 @@ impl<'a> BitStreamLSB<'a> {
         let value = self.bit_buffer & mask_for_bits_wanted;
         self.bit_buffer >>= bits_wanted;
         self.bits_in_buffer -= bits_wanted;
-        value
+        Some(value)
+    }
+
+    // Skips to the next byte boundary. The buffer never holds a whole unread byte, so it's just dropped.
+    pub fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bits_in_buffer = 0;
+    }
+}
+
+// This writes bits in the same order that BitStreamLSB reads them, for LZW and deflate.
+pub struct BitWriterLSB {
+    data: Vec<u8>,
+    bit_buffer: usize,
+    bits_in_buffer: usize,
+}
+
+impl BitWriterLSB {
+    pub fn new() -> Self {
+        BitWriterLSB { data: Vec::new(), bit_buffer: 0, bits_in_buffer: 0 }
+    }
+
+    pub fn write(&mut self, value: usize, bits: usize) {
+        assert!(bits <= 16);
+        self.bit_buffer += (value & ((1 << bits) - 1)) << self.bits_in_buffer;
+        self.bits_in_buffer += bits;
+        while self.bits_in_buffer >= 8 {
+            self.data.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bits_in_buffer -= 8;
+        }
+    }
+
+    // Pads with zeros up to the next byte boundary.
+    pub fn align(&mut self) {
+        if self.bits_in_buffer > 0 {
+            self.write(0, 8 - self.bits_in_buffer);
+        }
+    }
+
+    // For appending whole bytes once aligned.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        assert!(self.bits_in_buffer == 0);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.data
     }
 }
 
@@ -49,11 +98,20 @@ mod tests {
         // Next:                    210              543
         // Last:                                43210       cba98765
         let mut stream= BitStreamLSB::new(&input);
-        let first_5 = stream.next(5);
-        let next_6 = stream.next(6);
-        let last_13 = stream.next(13);
+        let first_5 = stream.next(5).unwrap();
+        let next_6 = stream.next(6).unwrap();
+        let last_13 = stream.next(13).unwrap();
         assert_eq!(first_5, 0b01010);
         assert_eq!(next_6, 0b111101);
         assert_eq!(last_13, 0b0011001100001);
     }
+
+    #[test]
+    fn test_writer() {
+        let mut writer = BitWriterLSB::new();
+        writer.write(0b01010, 5);
+        writer.write(0b111101, 6);
+        writer.write(0b0011001100001, 13);
+        assert_eq!(writer.finish(), vec![0b10101010, 0b00001111, 0b00110011]);
+    }
 }
@@ -18,13 +18,14 @@ impl<'a> BitStreamMSB<'a> {
         BitStreamMSB { data, index: 0, bit_buffer: 0, bits_in_buffer: 0 }
     }
 
-    pub fn next(&mut self, bits_wanted: usize) -> usize {
+    // None once the data runs out.
+    pub fn next(&mut self, bits_wanted: usize) -> Option<usize> {
         assert!(1 <= bits_wanted && bits_wanted <= 16);
 
         // Fetch more from data to top up the buffer.
         while bits_wanted > self.bits_in_buffer {
             // Grab another byte, add it to bit_buffer to the least significant end.
-            let byte = self.data[self.index];
+            let byte = *self.data.get(self.index)?;
             self.index += 1;
             self.bit_buffer = (self.bit_buffer << 8) + (byte as usize);
             self.bits_in_buffer += 8;
@@ -33,7 +34,39 @@ impl<'a> BitStreamMSB<'a> {
         let mask_for_bits_wanted = (1 << bits_wanted) - 1; // This trick sets the first N bits to 1.
         let value = (self.bit_buffer >> (self.bits_in_buffer - bits_wanted)) & mask_for_bits_wanted;
         self.bits_in_buffer -= bits_wanted;
-        value
+        Some(value)
+    }
+}
+
+// This writes bits in the same order that BitStreamMSB reads them, for Huffman.
+pub struct BitWriterMSB {
+    data: Vec<u8>,
+    bit_buffer: usize,
+    bits_in_buffer: usize,
+}
+
+impl BitWriterMSB {
+    pub fn new() -> Self {
+        BitWriterMSB { data: Vec::new(), bit_buffer: 0, bits_in_buffer: 0 }
+    }
+
+    pub fn write(&mut self, value: usize, bits: usize) {
+        assert!(bits <= 16);
+        self.bit_buffer = (self.bit_buffer << bits) | (value & ((1 << bits) - 1));
+        self.bits_in_buffer += bits;
+        while self.bits_in_buffer >= 8 {
+            self.data.push((self.bit_buffer >> (self.bits_in_buffer - 8)) as u8);
+            self.bits_in_buffer -= 8;
+        }
+        self.bit_buffer &= (1 << self.bits_in_buffer) - 1;
+    }
+
+    // Pads with zeros up to the next byte boundary.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.bits_in_buffer > 0 {
+            self.write(0, 8 - self.bits_in_buffer);
+        }
+        self.data
     }
 }
 
@@ -48,11 +81,20 @@ mod tests {
         // Next:                         012    345
         // Last:                                   01234    56789abc
         let mut stream= BitStreamMSB::new(&input);
-        let first_5 = stream.next(5);
-        let next_6 = stream.next(6);
-        let last_13 = stream.next(13);
+        let first_5 = stream.next(5).unwrap();
+        let next_6 = stream.next(6).unwrap();
+        let last_13 = stream.next(13).unwrap();
         assert_eq!(first_5, 0b10101);
         assert_eq!(next_6, 0b010000);
         assert_eq!(last_13, 0b0111100110011);
     }
+
+    #[test]
+    fn test_writer() {
+        let mut writer = BitWriterMSB::new();
+        writer.write(0b10101, 5);
+        writer.write(0b010000, 6);
+        writer.write(0b0111100110011, 13);
+        assert_eq!(writer.finish(), vec![0b10101010, 0b00001111, 0b00110011]);
+    }
 }
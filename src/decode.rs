@@ -1,47 +1,436 @@
 // This is responsible for coordinating the whole decoding process.
 
+use crate::audit;
+use crate::json;
+use crate::manifest;
 use crate::map;
+use crate::parallel;
+use crate::patch_files;
 use crate::resource_files;
 use crate::resource_reader;
+use crate::resource_source;
+use crate::resource_writer;
 use crate::view;
 use crate::renderer;
 use crate::picture;
+use crate::picture_assembler;
+use crate::picture_disassembler;
+use crate::sprite_sheet;
+use crate::svg;
+use crate::png;
+use crate::view_encoder;
+use crate::timing;
+use crate::version;
 
-pub fn decode(path: &str) {
+const DUMP_FOLDER: &str = "Output.dump";
+
+// Settings that change how resources are rendered.
+pub struct Options {
+    pub dither: renderer::Dither,
+    pub indexed: bool, // Write unscaled palette PNGs that keep the EGA colour indexes.
+    pub timing: timing::Timing, // For animations, unless the game's cycle speeds table says otherwise.
+    pub gif: bool, // Also write animations as GIFs, for places that don't show APNGs.
+    pub transparent: Option<u8>, // Colour that marks transparency when building views, otherwise the first unused one.
+    pub method: Option<usize>, // Compression when repacking, otherwise each resource keeps its own.
+    pub volume_size: Option<usize>, // Splits volumes when repacking.
+    pub threads: Option<usize>, // For decoding, otherwise one per core.
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { dither: renderer::Dither::Average, indexed: false, timing: timing::Timing::default(), gif: false, transparent: None, method: None, volume_size: None, threads: None }
+    }
+}
+
+// Resources are decoded in parallel, with progress reported in map order as they finish.
+pub fn decode(path: &str, options: &Options) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let cycle_speeds = timing::Table::read(path);
+
+    // Pictures, then views, with views numbered by their position in the map:
+    let pictures = map.entries.iter().enumerate().filter(|(_, e)| e.resource_type == map::ResourceType::Picture);
+    let views = map.entries.iter().enumerate().filter(|(_, e)| e.resource_type == map::ResourceType::View);
+    let jobs: Vec<(usize, &map::Entry)> = pictures.chain(views).collect();
+    let threads = options.threads.unwrap_or_else(parallel::default_threads);
+    // A corrupt resource is reported, and the rest are still decoded.
+    parallel::for_each(&jobs, threads, |&(vi, entry)| {
+        let (kind, result) = if entry.resource_type == map::ResourceType::Picture {
+            ("picture", decode_picture(entry, &files, options))
+        } else {
+            ("view", decode_view(vi, entry, &files, &cycle_speeds, options))
+        };
+        match result {
+            Ok(()) => format!("Decoded {}, resource number: {}, from: {}", kind, entry.resource_number, entry.source_name()),
+            Err(error) => format!("Couldn't decode {}, resource number: {}, from: {}: {}", kind, entry.resource_number, entry.source_name(), error),
+        }
+    }, |progress| println!("{}", progress));
+}
+
+fn decode_picture(entry: &map::Entry, files: &resource_files::Files, options: &Options) -> Result<(), String> {
+    let (_, _, resource) = resource_reader::read_first_copy(entry, files)?; // Pics all tend to be huffman.
+    let picture = picture::Picture::parse(&resource)?;
+    if options.indexed {
+        let name = format!("Output.picture.rn{}.f{}.indexed.png", entry.resource_number, entry.file);
+        std::fs::write(name, renderer::indexed_png_from_picture(&picture)).unwrap();
+        return Ok(())
+    }
+    let name = format!("Output.picture.rn{}.f{}.static.png", entry.resource_number, entry.file);
+    let png = renderer::png_from_picture(&picture, options.dither, 1); // Already on the pool.
+    std::fs::write(name, png).unwrap();
+    Ok(())
+}
+
+fn decode_view(vi: usize, entry: &map::Entry, files: &resource_files::Files, cycle_speeds: &timing::Table, options: &Options) -> Result<(), String> {
+    let (_, _, resource) = resource_reader::read_first_copy(entry, files)?; // Views tend to be LZW.
+    let view = view::View::parse(&resource)?;
+    for (li, l) in view.loops.iter().enumerate() {
+        if options.indexed {
+            for (ci, c) in l.cels.iter().enumerate() {
+                let name = format!("Output.view.rn{}.f{}.vi{}.li{}.ci{}.indexed.png", entry.resource_number, entry.file, vi, li, ci);
+                std::fs::write(name, renderer::indexed_png_from_cel(c)).unwrap();
+            }
+        } else if renderer::is_animation(l) {
+            // Animated.
+            let name = format!("Output.view.rn{}.f{}.vi{}.li{}.animation.png", entry.resource_number, entry.file, vi, li);
+            let timing = cycle_speeds.timing_for_view(entry.resource_number, &options.timing);
+            let png = renderer::apng_from_loop(l, &timing, 1);
+            std::fs::write(name, png).unwrap();
+            if options.gif {
+                let name = format!("Output.view.rn{}.f{}.vi{}.li{}.animation.gif", entry.resource_number, entry.file, vi, li);
+                std::fs::write(name, renderer::gif_from_loop(l, &timing)).unwrap();
+            }
+        } else {
+            // Not animated.
+            for (ci, c) in l.cels.iter().enumerate() {
+                let name = format!("Output.view.rn{}.f{}.vi{}.li{}.ci{}.static.png", entry.resource_number, entry.file, vi, li, ci);
+                let png = renderer::png_from_cel(c, 1);
+                std::fs::write(name, png).unwrap();
+            }
+        }
+    }
+    Ok(())
+}
+
+// Draws a stack of pictures on top of each other, as rooms do with overlays, and writes the visual, priority and control screens.
+pub fn composite(path: &str, numbers: &[usize], options: &Options) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let resources: Vec<Vec<u8>> = numbers.iter().map(|&number| {
+        let entry = map.entries.iter()
+            .find(|e| e.resource_type == map::ResourceType::Picture && e.resource_number == number)
+            .unwrap_or_else(|| panic!("Picture {} not found!", number));
+        resource_reader::read(entry, &files)
+    }).collect();
+    let picture = picture::Picture::composite(&resources).unwrap_or_else(|e| panic!("Couldn't draw the pictures: {}", e));
+    let stack: Vec<String> = numbers.iter().map(|n| n.to_string()).collect();
+    let stack = stack.join("+");
+    println!("Compositing pictures, resource numbers: {}", stack);
+    if options.indexed {
+        let name = format!("Output.picture.rn{}.visual.indexed.png", stack);
+        std::fs::write(name, renderer::indexed_png_from_picture(&picture)).unwrap();
+        let name = format!("Output.picture.rn{}.priority.indexed.png", stack);
+        std::fs::write(name, renderer::indexed_png_from_screen(&picture.priority)).unwrap();
+        let name = format!("Output.picture.rn{}.control.indexed.png", stack);
+        std::fs::write(name, renderer::indexed_png_from_screen(&picture.control)).unwrap();
+        return
+    }
+    let threads = options.threads.unwrap_or_else(parallel::default_threads); // For xBRZ, as it's just a few images.
+    let name = format!("Output.picture.rn{}.visual.static.png", stack);
+    std::fs::write(name, renderer::png_from_picture(&picture, options.dither, threads)).unwrap();
+    let name = format!("Output.picture.rn{}.priority.static.png", stack);
+    std::fs::write(name, renderer::png_from_screen(&picture.priority, threads)).unwrap();
+    let name = format!("Output.picture.rn{}.control.static.png", stack);
+    std::fs::write(name, renderer::png_from_screen(&picture.control, threads)).unwrap();
+}
+
+// Lists every resource, and which file it comes from, so you can see what patch files override.
+pub fn list(path: &str) {
     let map = map::Map::read(path);
-    let files = resource_files::Files::read(path);
+    for entry in &map.entries {
+        let copies: Vec<String> = entry.other_copies.iter().map(|l| l.name()).collect();
+        let copies = if copies.is_empty() { String::new() } else { format!(", also in: {}", copies.join(", ")) };
+        println!("{:?} {}, from: {}{}", entry.resource_type, entry.resource_number, entry.source_name(), copies);
+    }
+}
 
-    // Pictures:
+// Compares the copies of resources that are on multiple disks, which can expose bad floppy dumps.
+pub fn copies(path: &str) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let mut problems = 0;
     for entry in &map.entries {
-        if entry.resource_type != map::ResourceType::Picture { continue }
-        println!("Decoding picture, resource number: {}", entry.resource_number);
-        let resource = resource_reader::read(entry, &files); // Pics all tend to be huffman.
-        let picture = picture::Picture::parse(&resource);
-        let name = format!("Output.picture.rn{}.f{}.static.png", entry.resource_number, entry.file);
-        let png = renderer::png_from_picture(&picture);
-        std::fs::write(name, png).unwrap();
+        if entry.other_copies.is_empty() { continue }
+        let copies = resource_reader::read_copies(entry, &files);
+        for (location, result) in &copies {
+            if let Err(error) = result {
+                println!("{:?} {}: copy in {} {}", entry.resource_type, entry.resource_number, location.name(), error);
+                problems += 1;
+            }
+        }
+        for (first, location) in resource_reader::differing_copies(&copies) {
+            println!("{:?} {}: copy in {} differs from {}", entry.resource_type, entry.resource_number, location.name(), first.name());
+            problems += 1;
+        }
+    }
+    let copied = map.entries.iter().filter(|e| !e.other_copies.is_empty()).count();
+    println!("Checked {} resources with copies on multiple disks, found {} problems", copied, problems);
+}
+
+// Checks every resource reads, decompresses and parses, returning whether they all passed.
+pub fn audit(path: &str) -> bool {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let checks = audit::audit(&map, &files);
+    print!("{}", audit::table(&checks));
+    let failures = checks.iter().filter(|c| !c.problems.is_empty()).count();
+    println!("{} resources, {} passed, {} failed", checks.len(), checks.len() - failures, failures);
+    failures == 0
+}
+
+pub fn version(path: &str) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let map_data = files.read_at("resource.map", 0, files.size("resource.map").unwrap()).unwrap();
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or(path);
+    let version = version::detect(name, &map, &files, &map_data);
+    println!("Title: {}", version.title);
+    println!("Interpreter: {}", version.generation.name());
+    println!("Platform: {}", version.platform);
+    for reason in &version.reasons {
+        println!("* {}", reason);
     }
+}
+
+// Writes an inventory of every resource, for other tools to read rather than scraping the decoding output.
+pub fn manifest(path: &str) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    let items = manifest::manifest(&map, &files);
+    std::fs::write("Output.manifest.json", manifest::json(&map, &items)).unwrap();
+    std::fs::write("Output.manifest.csv", manifest::csv(&items)).unwrap();
+    let failures = items.iter().filter(|i| i.error.is_some()).count();
+    println!("Wrote Output.manifest.json and Output.manifest.csv, {} resources, {} with errors", items.len(), failures);
+}
+
+// Writes a textual listing of each picture's actions, optionally just the given picture number.
+pub fn disassemble(path: &str, number: Option<usize>) {
+    for_each_picture(path, number, |entry, resource| {
+        println!("Disassembling picture, resource number: {}", entry.resource_number);
+        match picture_disassembler::disassemble(resource) {
+            Ok(listing) => std::fs::write(format!("Output.picture.rn{}.f{}.listing.txt", entry.resource_number, entry.file), listing).unwrap(),
+            Err(error) => println!("Couldn't disassemble picture {}: {}", entry.resource_number, error),
+        }
+    });
+}
 
-    // Views:
-    for (vi, entry) in map.entries.iter().enumerate() {
+// Writes each picture as a vector SVG, optionally just the given picture number.
+pub fn svg(path: &str, number: Option<usize>) {
+    for_each_picture(path, number, |entry, resource| {
+        println!("Exporting picture as SVG, resource number: {}", entry.resource_number);
+        match svg::svg_from_picture_resource(resource) {
+            Ok(svg) => std::fs::write(format!("Output.picture.rn{}.f{}.svg", entry.resource_number, entry.file), svg).unwrap(),
+            Err(error) => println!("Couldn't export picture {}: {}", entry.resource_number, error),
+        }
+    });
+}
+
+// Packs each view into one sprite sheet with a JSON descriptor, optionally just the given view number.
+pub fn sheet(path: &str, number: Option<usize>) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    for entry in &map.entries {
         if entry.resource_type != map::ResourceType::View { continue }
-        println!("Decoding view, resource number: {}", entry.resource_number);
-        let resource = resource_reader::read(entry, &files); // Views tend to be LZW.
-        let view = view::View::parse(&resource);
-        for (li, l) in view.loops.iter().enumerate() {
-            if renderer::is_animation(l) {
-                // Animated.
-                let name = format!("Output.view.rn{}.f{}.vi{}.li{}.animation.png", entry.resource_number, entry.file, vi, li);
-                let png = renderer::apng_from_loop(l);
-                std::fs::write(name, png).unwrap();
-            } else {
-                // Not animated.
-                for (ci, c) in l.cels.iter().enumerate() {
-                    let name = format!("Output.view.rn{}.f{}.vi{}.li{}.ci{}.static.png", entry.resource_number, entry.file, vi, li, ci);
-                    let png = renderer::png_from_cel(c);
-                    std::fs::write(name, png).unwrap();
-                }
+        if number.is_some_and(|n| n != entry.resource_number) { continue }
+        println!("Packing view into sprite sheet, resource number: {}", entry.resource_number);
+        let resource = resource_reader::read(entry, &files);
+        let view = match view::View::parse(&resource) {
+            Ok(view) => view,
+            Err(error) => { println!("Couldn't pack view {}: {}", entry.resource_number, error); continue }
+        };
+        let sheet = sprite_sheet::pack(&view);
+        let image_name = format!("Output.view.rn{}.f{}.sheet.png", entry.resource_number, entry.file);
+        let json_name = format!("Output.view.rn{}.f{}.sheet.json", entry.resource_number, entry.file);
+        std::fs::write(&json_name, sheet.json(&image_name)).unwrap();
+        std::fs::write(&image_name, renderer::indexed_png_from_sprite_sheet(&sheet)).unwrap();
+    }
+}
+
+fn for_each_picture(path: &str, number: Option<usize>, mut f: impl FnMut(&map::Entry, &[u8])) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    for entry in &map.entries {
+        if entry.resource_type != map::ResourceType::Picture { continue }
+        if number.is_some_and(|n| n != entry.resource_number) { continue }
+        let resource = resource_reader::read(entry, &files);
+        f(entry, &resource);
+    }
+}
+
+// Builds a picture resource from a textual listing, saved as a patch file that the interpreter loads instead of the volume's copy.
+// https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/SCI0_resources
+pub fn assemble(listing_path: &str, number: usize) {
+    let listing = std::fs::read_to_string(listing_path).unwrap();
+    let resource = picture_assembler::assemble(&listing);
+    let type_number = map::ResourceType::Picture.number();
+    let patch = patch_files::patch_data(type_number, &resource);
+    let name = patch_files::name(type_number, number);
+    println!("Assembled picture, resource number: {}, saved as: {}", number, name);
+    std::fs::write(name, patch).unwrap();
+}
+
+// Builds a view resource from an (edited) sprite sheet and its descriptor, saved as a patch file.
+pub fn build_view(descriptor_path: &str, number: usize, options: &Options) {
+    let descriptor = std::fs::read_to_string(descriptor_path).unwrap();
+    let image_name = json::parse(&descriptor).ok()
+        .and_then(|d| d.get("image").and_then(|i| i.as_str()).map(|i| i.to_string()))
+        .expect("Sprite sheet descriptor is missing its image!");
+    let image_path = std::path::Path::new(descriptor_path).with_file_name(image_name);
+    let (width, _height, rgbas) = png::rgbas_from_png_data(&std::fs::read(image_path).unwrap());
+    let view = sprite_sheet::unpack(&descriptor, width, &rgbas);
+    let transparent = options.transparent
+        .or_else(|| view_encoder::unused_colour(&view))
+        .expect("Every EGA colour is used, so choose a transparent colour with --transparent!");
+    let resource = view_encoder::encode(&view, transparent);
+    let type_number = map::ResourceType::View.number();
+    let patch = patch_files::patch_data(type_number, &resource);
+    let name = patch_files::name(type_number, number);
+    println!("Built view, resource number: {}, saved as: {}", number, name);
+    std::fs::write(name, patch).unwrap();
+}
+
+// Writes every resource into a fresh map and volumes, optionally recompressed or split into smaller volumes.
+pub fn repack(path: &str, output_path: &str, options: &Options) {
+    let resources = resources_for_repacking(path, options);
+    write_volumes(&resources, output_path, options);
+}
+
+// Like repack, but with the resources replaced or added by patch files in the import folder, eg edited dumps.
+pub fn import(path: &str, import_path: &str, output_path: &str, options: &Options) {
+    let mut resources = resources_for_repacking(path, options);
+    let source = resource_source::Directory { path: import_path.to_string() };
+    let import_map = map::Map::read_patches(&source);
+    let files = resource_files::Files::new(Box::new(source));
+    for entry in &import_map.entries {
+        println!("Importing {:?} {}, from: {}", entry.resource_type, entry.resource_number, entry.source_name());
+        let data = resource_reader::read(entry, &files);
+        match resources.iter_mut().find(|r| r.type_number == entry.id >> 11 && r.resource_number == entry.resource_number) {
+            Some(existing) => existing.data = data, // Keeping its compression.
+            None => resources.push(resource_writer::Resource {
+                type_number: entry.id >> 11,
+                resource_number: entry.resource_number,
+                data,
+                method: options.method.unwrap_or(resource_reader::METHOD_LZW),
+            }),
+        }
+    }
+    write_volumes(&resources, output_path, options);
+}
+
+fn resources_for_repacking(path: &str, options: &Options) -> Vec<resource_writer::Resource> {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    map.entries.iter().map(|entry| {
+        let (header, data) = resource_reader::read_with_header(entry, &files);
+        // Volumes are written in the SCI0 layout, so methods are renumbered from the game's generation.
+        // Reading has already decompressed it, so the method is one that's supported.
+        let method = entry.generation.compression(header.method).and_then(|c| c.sci0_method()).unwrap();
+        resource_writer::Resource {
+            type_number: entry.id >> 11, // Rather than the ResourceType, so unknown types are repacked too.
+            resource_number: entry.resource_number,
+            data,
+            method: options.method.unwrap_or(method),
+        }
+    }).collect()
+}
+
+fn write_volumes(resources: &[resource_writer::Resource], output_path: &str, options: &Options) {
+    let packed = resource_writer::pack(resources, options.volume_size);
+    resource_writer::write(output_path, &packed);
+    println!("Packed {} resources into {} volumes in: {}", resources.len(), packed.volumes.len(), output_path);
+}
+
+// Writes every resource (or just the given number) decompressed, as patch files that ScummVM, SCI Companion and
+// the interpreter accept, and as stored in the volume with its header (.compressed), for reverse engineering.
+// Resources that can't be read are reported, and still get their .compressed copy if it's there.
+pub fn dump(path: &str, number: Option<usize>) {
+    dump_into(path, number, DUMP_FOLDER);
+}
+
+fn dump_into(path: &str, number: Option<usize>, folder: &str) {
+    let map = map::Map::read(path);
+    let files = resource_files::Files::open(path);
+    std::fs::create_dir_all(folder).unwrap();
+    let mut failures = 0;
+    for entry in &map.entries {
+        if number.is_some_and(|n| n != entry.resource_number) { continue }
+        let type_number = entry.id >> 11; // Rather than the ResourceType, so unknown types keep their number.
+        let name = format!("{}/{}", folder, patch_files::name(type_number, entry.resource_number));
+        println!("Dumping {:?} {}, as: {}", entry.resource_type, entry.resource_number, name);
+        match resource_reader::read_first_copy(entry, &files) {
+            Ok((_, _, resource)) => std::fs::write(&name, patch_files::patch_data(type_number, &resource)).unwrap(),
+            Err(error) => {
+                println!("Couldn't read {:?} {}, so it's only dumped as stored: {}", entry.resource_type, entry.resource_number, error);
+                failures += 1;
             }
         }
+        if let Some(stored) = resource_reader::read_stored(entry, &files) {
+            std::fs::write(format!("{}.compressed", name), stored).unwrap();
+        }
+    }
+    if failures > 0 {
+        println!("{} resources couldn't be read", failures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::{TempDir, SAMPLE_PATH};
+
+    #[test]
+    fn it_imports_what_it_dumps() {
+        let dir = TempDir::new("import");
+        let (path, dump_path, repacked_path) = (&dir.join("game"), &dir.join("dump"), &dir.join("repacked"));
+        // The sample, plus a resource of a type without a name:
+        let mut resources = resources_for_repacking(SAMPLE_PATH, &Options::default());
+        resources.push(resource_writer::Resource { type_number: 30, resource_number: 1, data: vec![4, 5, 6], method: resource_reader::METHOD_LZW });
+        write_volumes(&resources, path, &Options::default());
+        dump_into(path, None, dump_path);
+
+        // Edit two resources and add another:
+        let map = map::Map::read(path);
+        let files = resource_files::Files::open(path);
+        let edited = [&map.entries[0], map.entries.iter().find(|e| e.resource_type == map::ResourceType::Unknown).unwrap()];
+        for entry in edited {
+            let type_number = entry.id >> 11;
+            std::fs::write(format!("{}/{}", dump_path, patch_files::name(type_number, entry.resource_number)), patch_files::patch_data(type_number, &[1, 2, 3])).unwrap();
+        }
+        std::fs::write(format!("{}/text.999", dump_path), patch_files::patch_data(3, b"Added")).unwrap();
+        import(path, dump_path, repacked_path, &Options::default());
+
+        let repacked_map = map::Map::read(repacked_path);
+        let repacked_files = resource_files::Files::open(repacked_path);
+        assert_eq!(repacked_map.entries.len(), map.entries.len() + 1);
+        for entry in &map.entries {
+            let repacked = repacked_map.entries.iter().find(|e| e.id == entry.id).unwrap();
+            let expected = if edited.iter().any(|e| e.id == entry.id) { vec![1, 2, 3] } else { resource_reader::read(entry, &files) };
+            assert!(resource_reader::read(repacked, &repacked_files) == expected, "{:?} {} did not round trip", entry.resource_type, entry.resource_number);
+        }
+        let added = repacked_map.entries.iter().find(|e| e.resource_type == map::ResourceType::Text && e.resource_number == 999).unwrap();
+        assert_eq!(resource_reader::read(added, &repacked_files), b"Added");
+    }
+
+    #[test]
+    fn it_renumbers_methods_when_repacking() {
+        // Early SCI1 numbers Huffman as method 1, which SCI0 volumes would take as LZW.
+        let dir = TempDir::new("renumber");
+        let data: Vec<u8> = b"Roger Wilco, janitor. ".repeat(20);
+        let resource = resource_writer::Resource { type_number: map::ResourceType::View.number(), resource_number: 1, data, method: resource_reader::METHOD_HUFFMAN };
+        let mut packed = resource_writer::pack(&[resource], None);
+        packed.volumes[0][6] = 1;
+        resource_writer::write(&dir.path, &packed);
+        let resources = resources_for_repacking(&dir.path, &Options::default());
+        assert_eq!(resources[0].method, resource_reader::METHOD_HUFFMAN);
     }
 }
@@ -0,0 +1,216 @@
+// This file contains enough code to write animated GIFs without needing dependencies,
+// for places that don't reliably animate APNGs.
+// https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+// https://en.wikipedia.org/wiki/GIF#File_format
+
+use crate::bitstream_lsb::BitWriterLSB;
+use std::collections::HashMap;
+
+const MAX_CODE_SIZE: usize = 12;
+const MAX_CODES: usize = 1 << MAX_CODE_SIZE;
+
+// Frames are palette indexes, and the palette is RGBA (alpha is ignored, use the transparent index instead).
+// Delays are in 100ths of a second. Loop count is the number of plays, 0 means forever.
+pub fn gif_data(width: usize, height: usize, frames: &[Vec<u8>], palette: &[u32], transparent: Option<u8>, delays: &[u16], loop_count: u32) -> Vec<u8> {
+    assert!(!palette.is_empty() && palette.len() <= 256, "Palette must have 1-256 entries!");
+    assert!(delays.len() == frames.len(), "Need a delay for each frame!");
+    // The colour table must be a power of 2 in size, at least 2.
+    let table_bits = (1..=8).find(|b| (1 << b) >= palette.len()).unwrap();
+    let mut output = Vec::<u8>::new();
+
+    // Header and logical screen descriptor.
+    output.extend_from_slice(b"GIF89a");
+    append_lsb_u16(&mut output, width as u16);
+    append_lsb_u16(&mut output, height as u16);
+    output.push(0x80 | (((table_bits - 1) as u8) << 4) | ((table_bits - 1) as u8)); // Global colour table, colour resolution, table size.
+    output.push(0); // Background colour index.
+    output.push(0); // Pixel aspect ratio, 0 means none given.
+
+    // Global colour table.
+    for i in 0..(1 << table_bits) {
+        let colour = palette.get(i).copied().unwrap_or(0);
+        output.push((colour >> 24) as u8);
+        output.push((colour >> 16) as u8);
+        output.push((colour >> 8) as u8);
+    }
+
+    // NETSCAPE2.0 application extension, which counts repeats after the first play.
+    if loop_count != 1 {
+        output.extend_from_slice(&[0x21, 0xff, 11]);
+        output.extend_from_slice(b"NETSCAPE2.0");
+        output.extend_from_slice(&[3, 1]);
+        append_lsb_u16(&mut output, loop_count.saturating_sub(1).min(0xffff) as u16);
+        output.push(0); // Block terminator.
+    }
+
+    for (frame, delay) in frames.iter().zip(delays) {
+        // Graphic control extension.
+        // Disposal 2 restores the background (transparent) so transparent pixels don't show the previous frame.
+        output.extend_from_slice(&[0x21, 0xf9, 4]);
+        output.push((2 << 2) | if transparent.is_some() { 1 } else { 0 });
+        append_lsb_u16(&mut output, *delay);
+        output.push(transparent.unwrap_or(0));
+        output.push(0); // Block terminator.
+
+        // Image descriptor, covering the whole screen.
+        output.push(0x2c);
+        append_lsb_u16(&mut output, 0); // Left.
+        append_lsb_u16(&mut output, 0); // Top.
+        append_lsb_u16(&mut output, width as u16);
+        append_lsb_u16(&mut output, height as u16);
+        output.push(0); // No local colour table, not interlaced.
+
+        // Image data, in sub-blocks of up to 255 bytes.
+        let minimum_code_size = table_bits.max(2);
+        output.push(minimum_code_size as u8);
+        for block in lzw_compress(frame, minimum_code_size).chunks(255) {
+            output.push(block.len() as u8);
+            output.extend_from_slice(block);
+        }
+        output.push(0); // Block terminator.
+    }
+
+    output.push(0x3b); // Trailer.
+    output
+}
+
+// Variable-length-code LZW, as GIF decoders expect it. Codes start at minimum_code_size + 1 bits and grow to 12,
+// at which point the table is full and a clear code starts it over.
+fn lzw_compress(indexes: &[u8], minimum_code_size: usize) -> Vec<u8> {
+    let clear_code: usize = 1 << minimum_code_size;
+    let end_code: usize = clear_code + 1;
+    let mut writer = BitWriterLSB::new();
+    let mut table: HashMap<(usize, u8), usize> = HashMap::new(); // Prefix code + next index = code.
+    let mut next_code = end_code + 1;
+    let mut code_size = minimum_code_size + 1;
+    writer.write(clear_code, code_size);
+    let Some((&first, rest)) = indexes.split_first() else {
+        writer.write(end_code, code_size);
+        return writer.finish();
+    };
+    let mut prefix = first as usize;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue
+        }
+        writer.write(prefix, code_size);
+        // The decoder learns of each code one step behind, so the size grows after the code that fills it.
+        if next_code > (1 << code_size) - 1 && code_size < MAX_CODE_SIZE {
+            code_size += 1;
+        }
+        if next_code < MAX_CODES {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+        } else {
+            writer.write(clear_code, code_size);
+            table.clear();
+            next_code = end_code + 1;
+            code_size = minimum_code_size + 1;
+        }
+        prefix = index as usize;
+    }
+    writer.write(prefix, code_size);
+    if next_code > (1 << code_size) - 1 && code_size < MAX_CODE_SIZE {
+        code_size += 1;
+    }
+    writer.write(end_code, code_size);
+    writer.finish()
+}
+
+fn append_lsb_u16(vec: &mut Vec<u8>, value: u16) {
+    vec.push((value & 0xff) as u8);
+    vec.push((value >> 8) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitstream_lsb::BitStreamLSB;
+
+    // A decoder written from the spec rather than from the encoder, growing the code size once the table reaches it.
+    fn lzw_decompress(data: &[u8], minimum_code_size: usize) -> Vec<u8> {
+        let clear_code: usize = 1 << minimum_code_size;
+        let end_code: usize = clear_code + 1;
+        let mut reader = BitStreamLSB::new(data);
+        let initial_table: Vec<Vec<u8>> = (0..(end_code + 1)).map(|i| vec![i as u8]).collect();
+        let mut table = initial_table.clone();
+        let mut code_size = minimum_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output: Vec<u8> = Vec::new();
+        loop {
+            let code = reader.next(code_size).unwrap();
+            if code == clear_code {
+                table = initial_table.clone();
+                code_size = minimum_code_size + 1;
+                previous = None;
+                continue
+            }
+            if code == end_code { break }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code == table.len() => [previous.as_slice(), &previous[..1]].concat(),
+                _ => panic!("Code {} isn't in the table yet", code),
+            };
+            output.extend_from_slice(&entry);
+            if let Some(previous) = previous && table.len() < MAX_CODES {
+                table.push([previous.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == (1 << code_size) && code_size < MAX_CODE_SIZE { code_size += 1 }
+            previous = Some(entry);
+        }
+        output
+    }
+
+    #[test]
+    fn it_round_trips_lzw() {
+        // Enough varied data to grow to 12 bit codes and fill the table a few times over.
+        let mut seed: u32 = 1;
+        let noise: Vec<u8> = (0..20_000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) % 16) as u8
+        }).collect();
+        for (indexes, minimum_code_size) in [(vec![], 2), (vec![3], 2), (vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1], 2), (noise.clone(), 4), (noise, 8)] {
+            assert_eq!(lzw_decompress(&lzw_compress(&indexes, minimum_code_size), minimum_code_size), indexes);
+        }
+    }
+
+    #[test]
+    fn it_writes_the_loop_count_as_repeats() {
+        let netscape = |loop_count| {
+            let data = gif_data(1, 1, &[vec![0]], &[0xffffffff], None, &[10], loop_count);
+            let start = data.windows(11).position(|w| w == b"NETSCAPE2.0")?;
+            Some(data[(start + 11)..(start + 16)].to_vec())
+        };
+        assert_eq!(netscape(0), Some(vec![3, 1, 0, 0, 0])); // Forever.
+        assert_eq!(netscape(1), None); // Plays once without it.
+        assert_eq!(netscape(3), Some(vec![3, 1, 2, 0, 0]));
+        assert_eq!(netscape(100_000), Some(vec![3, 1, 0xff, 0xff, 0]));
+    }
+
+    #[test]
+    fn it_writes_frames_that_decode() {
+        let frames = vec![vec![0, 1, 2, 2, 1, 0], vec![2, 2, 2, 0, 0, 0]];
+        let data = gif_data(3, 2, &frames, &[0x000000ff, 0xff0000ff, 0x00ff00ff], Some(0), &[5, 7], 0);
+        assert_eq!(&data[..6], b"GIF89a");
+        assert_eq!(data[10], 0x80 | (1 << 4) | 1); // 3 colours round up to a table of 4.
+        assert_eq!(*data.last().unwrap(), 0x3b);
+        // Each frame is a graphic control extension, image descriptor, minimum code size then sub-blocks.
+        let mut offset = 13 + 4 * 3 + 19;
+        for (frame, delay) in frames.iter().zip([5, 7]) {
+            assert_eq!(&data[offset..(offset + 8)], [0x21, 0xf9, 4, (2 << 2) | 1, delay, 0, 0, 0]);
+            offset += 8 + 10;
+            let minimum_code_size = data[offset] as usize;
+            offset += 1;
+            let mut compressed: Vec<u8> = Vec::new();
+            while data[offset] != 0 {
+                let length = data[offset] as usize;
+                compressed.extend_from_slice(&data[(offset + 1)..(offset + 1 + length)]);
+                offset += 1 + length;
+            }
+            offset += 1;
+            assert_eq!(&lzw_decompress(&compressed, minimum_code_size), frame);
+        }
+        assert_eq!(offset, data.len() - 1);
+    }
+}
@@ -1,16 +1,20 @@
-// This is responsible for decompressing Huffman-packed data.
+// This is responsible for decompressing and compressing Huffman-packed data.
 // https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/decompressor.cpp
 // https://sciwiki.sierrahelp.com/index.php/SCI_Specifications:_Chapter_2_-_Resource_files#Decompression_algorithm_HUFFMAN
 // https://github.com/icefallgames/SCICompanion/blob/master/SCICompanionLib/Src/Util/Codec.cpp
 
-use crate::bitstream_msb::{self, BitStreamMSB};
+use crate::bitstream_msb::{self, BitStreamMSB, BitWriterMSB};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
-pub fn decompress(src: &[u8], decompressed_size: usize) -> Vec<u8> {
+// Corrupt data gives an error, such as a node pointing past the table, or running out before the end.
+pub fn decompress(src: &[u8], decompressed_size: usize) -> Result<Vec<u8>, String> {
     // Get the header info from the data:
+    if src.len() < 2 { return Err("Huffman header is missing".to_string()) }
     let node_count = src[0] as usize;
     let terminator = src[1];
     let node_size = node_count * 2;
-    let nodes_data = &src[2..(2 + node_size)];
+    let nodes_data = src.get(2..(2 + node_size)).ok_or(format!("Huffman data is too short for {} nodes", node_count))?;
     let data = &src[(2 + node_size)..];
 
     // Parse it into nodes / prep it into a bitstream:
@@ -20,17 +24,18 @@ pub fn decompress(src: &[u8], decompressed_size: usize) -> Vec<u8> {
 
     // Loop pulling a byte at a time until we hit a terminator or decompress enough bytes:
     loop {
-        let (byte, is_bitstream_literal) = get_next_byte(&mut bitstream, &nodes);
+        let (byte, is_bitstream_literal) = get_next_byte(&mut bitstream, &nodes)
+            .ok_or(format!("Huffman data is corrupt after {} of {} bytes", out.len(), decompressed_size))?;
         if is_bitstream_literal && byte == terminator { break }
         if out.len() >= decompressed_size { break } // In case there's no terminator.
         out.push(byte);
     }
 
     if out.len() != decompressed_size {
-        panic!("Huffman decoding incorrect length: {}, expected {}", out.len(), decompressed_size);
+        return Err(format!("Huffman decoding incorrect length: {}, expected {}", out.len(), decompressed_size));
     }
 
-    out
+    Ok(out)
 }
 
 struct Node {
@@ -46,14 +51,195 @@ impl Node {
 
 // Bool true = from bitstream literal (right bit but 0 sibling).
 // Bool false = from node value (no sibling left nor right).
-fn get_next_byte(bitstream: &mut BitStreamMSB, nodes: &[Node]) -> (u8, bool) {
-    let node = &nodes[0];
-    if node.siblings == 0 { return (node.value, false) }
-    let is_low_nibble = bitstream.next(1) != 0;
+// None if the data runs out or a sibling is past the end of the table.
+fn get_next_byte(bitstream: &mut BitStreamMSB, nodes: &[Node]) -> Option<(u8, bool)> {
+    let node = nodes.first()?;
+    if node.siblings == 0 { return Some((node.value, false)) }
+    let is_low_nibble = bitstream.next(1)? != 0;
     let sibling = if is_low_nibble { node.siblings & 0x0f } else { node.siblings >> 4 };
     if sibling == 0 {
-        let literal_token = bitstream.next(8) as u8;
-        return (literal_token, true);
+        let literal_token = bitstream.next(8)? as u8;
+        return Some((literal_token, true));
     } 
-    get_next_byte(bitstream, &nodes[(sibling as usize)..])
+    get_next_byte(bitstream, nodes.get((sibling as usize)..)?)
+}
+
+// Produces data that decompress (and the SCI0 interpreter) accepts.
+// The node table can only point up to 15 nodes ahead, and has at most 255 nodes, so not every Huffman tree fits.
+// Instead the most frequent bytes get codes, and the rest are escaped as literals, which the terminator also uses.
+// Every number of coded bytes is tried, and whichever tree fits and gives the smallest output wins.
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut frequencies: [usize; 256] = [0; 256];
+    for &b in src {
+        frequencies[b as usize] += 1;
+    }
+    let mut by_frequency: Vec<u8> = (0..=255).filter(|&b| frequencies[b as usize] > 0).collect();
+    by_frequency.sort_by_key(|&b| Reverse(frequencies[b as usize]));
+    if by_frequency.is_empty() {
+        by_frequency.push(0); // The tree needs at least one byte to code.
+    }
+
+    let mut best: Option<(usize, Vec<Tree>, usize)> = None; // Cost in bits, tree nodes, root.
+    for coded_count in 1..=by_frequency.len().min(MAX_NODES / 2) {
+        let coded = &by_frequency[..coded_count];
+        let escaped_count: usize = by_frequency[coded_count..].iter().map(|&b| frequencies[b as usize]).sum();
+        let (tree, root) = build_tree(coded, &frequencies, escaped_count + 1); // Plus one for the terminator.
+        if !fits(&tree, root) { continue }
+        let mut lengths: Vec<(Option<u8>, usize)> = Vec::new();
+        code_lengths(&tree, root, 0, &mut lengths);
+        let mut cost = 16 + size(&tree, root) * 16; // Header and node table.
+        for (symbol, length) in lengths {
+            cost += match symbol {
+                Some(b) => frequencies[b as usize] * length,
+                None => (escaped_count + 1) * (length + 8),
+            };
+        }
+        if best.as_ref().is_none_or(|(best_cost, _, _)| cost < *best_cost) {
+            best = Some((cost, tree, root));
+        }
+    }
+    let (_, tree, root) = best.expect("A tree with one coded byte always fits!");
+
+    // Lay out the node table, and work out each code as it goes.
+    let mut nodes: Vec<(u8, u8)> = Vec::new();
+    let mut codes: Vec<Option<Vec<usize>>> = vec![None; 256];
+    let mut escape_code: Vec<usize> = Vec::new();
+    layout(&tree, root, &mut Vec::new(), &mut nodes, &mut codes, &mut escape_code);
+
+    // Use a coded byte as the terminator, so an escaped literal can never be mistaken for it.
+    let terminator = by_frequency[0];
+    let mut out: Vec<u8> = vec![nodes.len() as u8, terminator];
+    for (value, siblings) in &nodes {
+        out.push(*value);
+        out.push(*siblings);
+    }
+    let mut writer = BitWriterMSB::new();
+    let write_bits = |writer: &mut BitWriterMSB, bits: &[usize]| bits.iter().for_each(|&b| writer.write(b, 1));
+    for &b in src {
+        match &codes[b as usize] {
+            Some(code) => write_bits(&mut writer, code),
+            None => {
+                write_bits(&mut writer, &escape_code);
+                writer.write(b as usize, 8);
+            }
+        }
+    }
+    write_bits(&mut writer, &escape_code);
+    writer.write(terminator as usize, 8);
+    out.extend(writer.finish());
+    out
+}
+
+const MAX_NODES: usize = 255;
+const MAX_SIBLING_OFFSET: usize = 15;
+
+enum Tree {
+    Leaf(u8),
+    Escape, // Not a node in the table, it's a 0 low nibble.
+    Branch(usize, usize),
+}
+
+// Plain Huffman tree building, returning the nodes and the index of the root.
+fn build_tree(coded: &[u8], frequencies: &[usize; 256], escape_frequency: usize) -> (Vec<Tree>, usize) {
+    let mut tree: Vec<Tree> = vec![Tree::Escape];
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = BinaryHeap::new(); // Frequency, node index.
+    heap.push(Reverse((escape_frequency, 0)));
+    for &b in coded {
+        heap.push(Reverse((frequencies[b as usize], tree.len())));
+        tree.push(Tree::Leaf(b));
+    }
+    while heap.len() > 1 {
+        let Reverse((frequency_a, a)) = heap.pop().unwrap();
+        let Reverse((frequency_b, b)) = heap.pop().unwrap();
+        heap.push(Reverse((frequency_a + frequency_b, tree.len())));
+        tree.push(Tree::Branch(a, b));
+    }
+    let root = tree.len() - 1;
+    (tree, root)
+}
+
+// How many table nodes a subtree takes.
+fn size(tree: &[Tree], node: usize) -> usize {
+    match tree[node] {
+        Tree::Leaf(_) => 1,
+        Tree::Escape => 0,
+        Tree::Branch(a, b) => 1 + size(tree, a) + size(tree, b),
+    }
+}
+
+// Each branch is followed by its smaller child's subtree, then its bigger child, which must be within reach.
+fn fits(tree: &[Tree], root: usize) -> bool {
+    if size(tree, root) > MAX_NODES { return false }
+    tree.iter().all(|t| match *t {
+        Tree::Branch(a, b) => size(tree, a).min(size(tree, b)) < MAX_SIBLING_OFFSET,
+        _ => true,
+    })
+}
+
+// Returns the code length of each byte, or None for the escape.
+fn code_lengths(tree: &[Tree], node: usize, depth: usize, lengths: &mut Vec<(Option<u8>, usize)>) {
+    match tree[node] {
+        Tree::Leaf(b) => lengths.push((Some(b), depth)),
+        Tree::Escape => lengths.push((None, depth)),
+        Tree::Branch(a, b) => {
+            code_lengths(tree, a, depth + 1, lengths);
+            code_lengths(tree, b, depth + 1, lengths);
+        }
+    }
+}
+
+// Writes the nodes in pre-order. A 0 bit takes the high nibble, a 1 bit the low nibble, which is 0 for the escape.
+fn layout(tree: &[Tree], node: usize, path: &mut Vec<usize>, nodes: &mut Vec<(u8, u8)>, codes: &mut [Option<Vec<usize>>], escape_code: &mut Vec<usize>) {
+    match tree[node] {
+        Tree::Leaf(b) => {
+            nodes.push((b, 0));
+            codes[b as usize] = Some(path.clone());
+        }
+        Tree::Escape => *escape_code = path.clone(),
+        Tree::Branch(a, b) => {
+            let position = nodes.len();
+            nodes.push((0, 0));
+            let (first, second) = if size(tree, a) <= size(tree, b) { (a, b) } else { (b, a) };
+            // The escape has to be on the 1 side, otherwise the first child is.
+            let zero = if matches!(tree[first], Tree::Escape) { second } else { first };
+            for child in [first, second] {
+                let offset = nodes.len() - position;
+                let bit = if child == zero { 0 } else { 1 };
+                if !matches!(tree[child], Tree::Escape) {
+                    nodes[position].1 |= if bit == 0 { (offset as u8) << 4 } else { offset as u8 };
+                }
+                path.push(bit);
+                layout(tree, child, path, nodes, codes, escape_code);
+                path.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::assert_sample_resources_round_trip;
+
+    #[test]
+    fn it_round_trips_sample_resources() {
+        assert_sample_resources_round_trip(compress, decompress);
+    }
+
+    #[test]
+    fn it_round_trips_edge_cases() {
+        let uniform: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        for data in [vec![], vec![9], vec![3; 100], uniform] {
+            assert!(decompress(&compress(&data), data.len()).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn it_rejects_corrupt_data() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 13) as u8).collect();
+        let compressed = compress(&data);
+        assert!(decompress(&compressed[..compressed.len() / 2], data.len()).is_err());
+        assert!(decompress(&[1], 10).is_err());
+        assert!(decompress(&[1, 0, 0, 0x10, 0], 10).is_err()); // The only node points past the end of the table.
+    }
 }
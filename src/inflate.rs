@@ -0,0 +1,195 @@
+// This is responsible for decompressing RFC1951 deflate streams, for reading PNGs back in.
+// It decodes Huffman codes a bit at a time using the code length counts, like zlib's 'puff' reference decoder,
+// which is slower than table lookups but much simpler.
+// https://datatracker.ietf.org/doc/html/rfc1951
+// https://github.com/madler/zlib/blob/master/contrib/puff/puff.c
+
+use crate::bitstream_lsb::BitStreamLSB;
+
+const LENGTH_BASE: [usize; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [usize; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [usize; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [usize; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS: usize = 15;
+
+// Skips the 2 byte header, and ignores the Adler-32 checksum at the end.
+// https://datatracker.ietf.org/doc/html/rfc1950
+pub fn inflate_zlib(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 2 || data[0] & 0xf != 8 { return Err("Not a zlib deflate stream".to_string()) }
+    if data[1] & 0x20 != 0 { return Err("Zlib preset dictionaries aren't supported".to_string()) }
+    inflate(&data[2..])
+}
+
+// Corrupt data gives an error, eg codes that aren't in the table, or a stream that ends early.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut stream = BitStreamLSB::new(data);
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let is_final = next(&mut stream, 1)? == 1;
+        match next(&mut stream, 2)? {
+            0 => stored(&mut stream, &mut out)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                codes(&mut stream, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut stream)?;
+                codes(&mut stream, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("Invalid deflate block type".to_string()),
+        }
+        if is_final { break }
+    }
+    Ok(out)
+}
+
+fn next(stream: &mut BitStreamLSB, bits: usize) -> Result<usize, String> {
+    stream.next(bits).ok_or_else(|| "Deflate stream ends early".to_string())
+}
+
+fn stored(stream: &mut BitStreamLSB, out: &mut Vec<u8>) -> Result<(), String> {
+    stream.align();
+    let length = next(stream, 16)?;
+    let complement = next(stream, 16)?;
+    if length != !complement & 0xffff { return Err("Stored block length doesn't match its complement".to_string()) }
+    for _ in 0..length {
+        out.push(next(stream, 8)? as u8);
+    }
+    Ok(())
+}
+
+// Canonical Huffman codes, as the number of codes of each length and the symbols in code order.
+struct Huffman {
+    counts: [usize; MAX_BITS + 1],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[usize]) -> Huffman {
+        let mut counts = [0; MAX_BITS + 1];
+        for &l in lengths {
+            counts[l] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; MAX_BITS + 2];
+        for bits in 1..=MAX_BITS {
+            offsets[bits + 1] = offsets[bits] + counts[bits];
+        }
+        let mut symbols: Vec<usize> = vec![0; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l]] = symbol;
+                offsets[l] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    // Codes are packed most significant bit first, so read a bit at a time until it matches a length.
+    fn decode(&self, stream: &mut BitStreamLSB) -> Result<usize, String> {
+        let mut code = 0; // Bits read so far.
+        let mut first = 0; // First code of the current length.
+        let mut index = 0; // Index of the first code of the current length in symbols.
+        for bits in 1..=MAX_BITS {
+            code |= next(stream, 1)?;
+            let count = self.counts[bits];
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code in deflate stream".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = vec![8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(stream: &mut BitStreamLSB) -> Result<(Huffman, Huffman), String> {
+    let literal_count = next(stream, 5)? + 257;
+    let distance_count = next(stream, 5)? + 1;
+    let code_length_count = next(stream, 4)? + 4;
+    let mut code_length_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = next(stream, 3)?;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths);
+
+    // The literal and distance lengths are run-length encoded together.
+    let mut lengths: Vec<usize> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(stream)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol, 1),
+            16 => (*lengths.last().ok_or("Repeat with no previous length")?, 3 + next(stream, 2)?),
+            17 => (0, 3 + next(stream, 3)?),
+            _ => (0, 11 + next(stream, 7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths.len() != literal_count + distance_count { return Err("Code lengths overflow".to_string()) }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn codes(stream: &mut BitStreamLSB, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(stream)?;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue
+        }
+        if symbol == 256 { return Ok(()) } // End of block.
+        let symbol = symbol - 257;
+        if symbol >= 29 { return Err("Invalid length code".to_string()) }
+        let length = LENGTH_BASE[symbol] + extra_bits(stream, LENGTH_EXTRA_BITS[symbol])?;
+        let symbol = distances.decode(stream)?;
+        if symbol >= 30 { return Err("Invalid distance code".to_string()) }
+        let distance = DISTANCE_BASE[symbol] + extra_bits(stream, DISTANCE_EXTRA_BITS[symbol])?;
+        if distance > out.len() { return Err("Distance is before the start of the output".to_string()) }
+        let start = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]); // Can overlap what's being written, for runs.
+        }
+    }
+}
+
+fn extra_bits(stream: &mut BitStreamLSB, bits: usize) -> Result<usize, String> {
+    if bits == 0 { Ok(0) } else { next(stream, bits) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_inflates_fixed_codes() {
+        // "abcabcabc" as made by zlib.compress at level 9.
+        let data: Vec<u8> = vec![0x78, 0xda, 0x4b, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00, 0x11, 0x3d, 0x03, 0x73];
+        assert_eq!(inflate_zlib(&data).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn it_rejects_corrupt_data() {
+        let data: Vec<u8> = vec![0x78, 0xda, 0x4b, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00, 0x11, 0x3d, 0x03, 0x73];
+        assert!(inflate_zlib(&data[..6]).is_err()); // Ends early.
+        assert!(inflate(&[0x07]).is_err()); // Block type 3.
+        assert!(inflate(&[0x01, 0x01, 0x00, 0x00, 0x00]).is_err()); // Stored length doesn't match its complement.
+        assert!(inflate(&[0x03, 0x00]).is_ok()); // An empty fixed block.
+        // Every truncation and byte of the fixed code stream, to check nothing panics:
+        for i in 2..data.len() {
+            let _ = inflate_zlib(&data[..i]);
+            for value in [0x00, 0xff, 0x55] {
+                let mut corrupt = data.clone();
+                corrupt[i] = value;
+                let _ = inflate_zlib(&corrupt);
+            }
+        }
+    }
+}
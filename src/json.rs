@@ -0,0 +1,223 @@
+// This is a minimal JSON reader, enough to read back the descriptors this tool writes without a dependency.
+// Writing is done with format!, this just helps with the strings.
+// https://www.json.org/json-en.html
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>), // Kept in order.
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut parser = Parser { chars, position: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < parser.chars.len() {
+        return Err(format!("Unexpected trailing content at {}", parser.position));
+    }
+    Ok(value)
+}
+
+// Quotes and escapes a string for writing, eg a file name.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return Err(format!("Expected '{}' at {}", c, self.position));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(format!("Unexpected content at {}", self.position)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        let end = self.position + word.len();
+        if end > self.chars.len() || self.chars[self.position..end].iter().collect::<String>() != word {
+            return Err(format!("Expected {} at {}", word, self.position));
+        }
+        self.position = end;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        while self.position < self.chars.len() && "+-0123456789.eE".contains(self.chars[self.position]) {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().map(Value::Number).map_err(|_| format!("Bad number at {}: {}", start, text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let Some(&c) = self.chars.get(self.position) else { return Err("Unterminated string".to_string()) };
+            self.position += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let Some(&escaped) = self.chars.get(self.position) else { return Err("Unterminated string".to_string()) };
+                    self.position += 1;
+                    match escaped {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let hex: String = self.chars.get(self.position..(self.position + 4)).ok_or("Bad unicode escape")?.iter().collect();
+                            let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("Bad unicode escape: {}", hex))?;
+                            s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                            self.position += 4;
+                        }
+                        other => s.push(other), // Quote, backslash, slash.
+                    }
+                }
+                _ => s.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values: Vec<Value> = Vec::new();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => { self.position += 1; return Ok(Value::Array(values)) }
+                _ => return Err(format!("Expected ',' or ']' at {}", self.position)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => { self.position += 1; return Ok(Value::Object(fields)) }
+                _ => return Err(format!("Expected ',' or '}}' at {}", self.position)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses() {
+        let value = parse(r#"{ "a": [1, -2.5e1, true, null], "b": "x\"yA" }"#).unwrap();
+        let a = value.get("a").unwrap().as_array().unwrap();
+        assert_eq!(a[1].as_f64(), Some(-25.0));
+        assert_eq!(a[2].as_bool(), Some(true));
+        assert_eq!(a[3], Value::Null);
+        assert_eq!(value.get("b").unwrap().as_str(), Some("x\"yA"));
+    }
+
+    #[test]
+    fn it_quotes_strings() {
+        let text = "a \"b\" \\ c\n\u{1}";
+        assert_eq!(parse(&quote(text)).unwrap().as_str(), Some(text));
+    }
+}
@@ -1,9 +1,11 @@
-// This is responsible for decompressing LZW data.
+// This is responsible for decompressing and compressing LZW data.
 // https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/decompressor.cpp
 
-use crate::bitstream_lsb;
+use crate::bitstream_lsb::{self, BitWriterLSB};
+use std::collections::HashMap;
 
-pub fn decompress(src: &[u8], decompressed_size: usize) -> Vec<u8> {
+// Corrupt data gives an error, such as a code that isn't in the table yet, or running out before the end.
+pub fn decompress(src: &[u8], decompressed_size: usize) -> Result<Vec<u8>, String> {
     const INITIAL_CODE_BIT_LENGTH: usize = 9;
     const INITIAL_TABLE_SIZE: usize = 258;
     const INITIAL_CODE_LIMIT: usize = 512; // SCI0.
@@ -19,10 +21,11 @@ pub fn decompress(src: &[u8], decompressed_size: usize) -> Vec<u8> {
     let mut out: Vec<u8> = Vec::new();
 
 	while out.len() < decompressed_size {
-        let code = stream.next(code_bit_length);
+        let code = stream.next(code_bit_length)
+            .ok_or(format!("LZW data ran out after {} of {} bytes", out.len(), decompressed_size))?;
 
 		if code >= table_size {
-            panic!("LZW code {} exceeds table size {}", code, table_size);
+            return Err(format!("LZW code {} exceeds table size {}", code, table_size));
 		}
 
 		if code == 257 { break } // Terminator.
@@ -66,5 +69,82 @@ pub fn decompress(src: &[u8], decompressed_size: usize) -> Vec<u8> {
 		table_size += 1;
     }
 
-    out
+    Ok(out)
+}
+
+// Produces a stream that decompress (and the SCI0 interpreter) accepts.
+// It mirrors the decoder's table exactly: every code adds an entry of its string plus the next string's first byte,
+// and the code size grows when the table reaches the limit. Once the table is full, it resets.
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    const INITIAL_CODE_BIT_LENGTH: usize = 9;
+    const INITIAL_TABLE_SIZE: usize = 258;
+    const MAX_TABLE_SIZE: usize = 4096;
+    const RESET: usize = 256;
+    const TERMINATOR: usize = 257;
+
+    let mut writer = BitWriterLSB::new();
+    let mut code_bit_length = INITIAL_CODE_BIT_LENGTH;
+    let mut table_size = INITIAL_TABLE_SIZE;
+    let mut table: HashMap<(usize, u8), usize> = HashMap::new(); // Code of a string + next byte = code of the longer string.
+    let Some((&first, rest)) = src.split_first() else {
+        writer.write(TERMINATOR, code_bit_length);
+        return writer.finish();
+    };
+    let mut current = first as usize; // Code of the longest string matched so far.
+    for &byte in rest {
+        if let Some(&code) = table.get(&(current, byte)) {
+            current = code;
+            continue
+        }
+        writer.write(current, code_bit_length);
+        if table_size >= MAX_TABLE_SIZE {
+            writer.write(RESET, code_bit_length);
+            code_bit_length = INITIAL_CODE_BIT_LENGTH;
+            table_size = INITIAL_TABLE_SIZE;
+            table.clear();
+        } else {
+            if table_size == 1 << code_bit_length && code_bit_length < 12 {
+                code_bit_length += 1;
+            }
+            table.insert((current, byte), table_size);
+            table_size += 1;
+        }
+        current = byte as usize;
+    }
+    writer.write(current, code_bit_length);
+    // The decoder adds an entry after the last code too, which can grow the code size for the terminator.
+    if table_size < MAX_TABLE_SIZE && table_size == 1 << code_bit_length && code_bit_length < 12 {
+        code_bit_length += 1;
+    }
+    writer.write(TERMINATOR, code_bit_length);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::assert_sample_resources_round_trip;
+
+    #[test]
+    fn it_round_trips_sample_resources() {
+        assert_sample_resources_round_trip(compress, decompress);
+    }
+
+    #[test]
+    fn it_round_trips_a_full_table() {
+        // Varied enough to fill the table and reset several times, with runs to exercise the KwKwK case.
+        let data: Vec<u8> = (0..100_000u32).map(|i| if i % 1000 < 100 { 7 } else { (i.wrapping_mul(2654435761) >> 24) as u8 }).collect();
+        assert!(decompress(&compress(&data), data.len()).unwrap() == data);
+        // Asking for more than there is makes it stop at the terminator instead, so this checks that's readable.
+        assert!(decompress(&compress(&data), data.len() + 1).unwrap() == data);
+        assert!(decompress(&compress(&data[..300]), 301).unwrap() == data[..300]);
+    }
+
+    #[test]
+    fn it_rejects_corrupt_data() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let compressed = compress(&data);
+        assert!(decompress(&compressed[..compressed.len() / 2], data.len()).is_err());
+        assert_eq!(decompress(&[0x2c, 0x01], 10), Err("LZW code 300 exceeds table size 258".to_string()));
+    }
 }
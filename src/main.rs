@@ -1,27 +1,140 @@
+mod audit;
 mod bitstream_lsb;
 mod bitstream_msb;
 mod decode;
+mod gif;
 mod huffman;
+mod inflate;
+mod json;
 mod lzw;
+mod manifest;
 mod map;
 mod palette;
+mod parallel;
+mod patch_files;
+mod picture_assembler;
+mod picture_disassembler;
 mod picture_splitter;
 mod picture;
 mod png;
 mod renderer;
 mod resource_files;
 mod resource_reader;
+mod resource_source;
+mod resource_writer;
+mod sprite_sheet;
+mod svg;
+mod timing;
+mod version;
 mod view;
+mod view_encoder;
 mod xbrz;
+mod zip;
 
 fn main() {
     println!("-=[ SCI Quest Decoder ]=-");
 
-    let args: Vec<String> = std::env::args().collect();
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().partition(|a| a.starts_with("--"));
+    let options = options_from_flags(&flags);
     if args.len() < 2 {
         println!("Usage:");
-        println!("sci-quest-decoder /Path/To/SQ3");
+        println!("sci-quest-decoder /Path/To/SQ3 [--dither=average|exact|exact-after-scaling] [--indexed] [--ticks=12[,12...]] [--loops=0] [--gif] [--threads=N]");
+        println!("sci-quest-decoder /Path/To/SQ3 list");
+        println!("sci-quest-decoder /Path/To/SQ3 copies");
+        println!("sci-quest-decoder /Path/To/SQ3 audit");
+        println!("sci-quest-decoder /Path/To/SQ3 version");
+        println!("sci-quest-decoder /Path/To/SQ3 manifest");
+        println!("sci-quest-decoder /Path/To/SQ3 disassemble [picture number]");
+        println!("sci-quest-decoder /Path/To/SQ3 svg [picture number]");
+        println!("sci-quest-decoder /Path/To/SQ3 sheet [view number]");
+        println!("sci-quest-decoder /Path/To/SQ3 composite <picture number> <overlay picture number>...");
+        println!("sci-quest-decoder /Path/To/SQ3 dump [resource number]");
+        println!("sci-quest-decoder /Path/To/SQ3 repack /Path/To/Output [--method=none|lzw|huffman] [--volume-size=bytes]");
+        println!("sci-quest-decoder /Path/To/SQ3 import /Path/To/Dump /Path/To/Output [--method=none|lzw|huffman] [--volume-size=bytes]");
+        println!("sci-quest-decoder assemble /Path/To/Listing.txt <picture number>");
+        println!("sci-quest-decoder build-view /Path/To/Output.view.rnN.fN.sheet.json <view number> [--transparent=0-15]");
+    } else if args[1] == "assemble" {
+        let (Some(listing_path), Some(number)) = (args.get(2), args.get(3)) else {
+            println!("Assemble needs a listing path and picture number!");
+            return
+        };
+        decode::assemble(listing_path, number.parse().expect("Picture number must be a number!"));
+    } else if args[1] == "build-view" {
+        let (Some(descriptor_path), Some(number)) = (args.get(2), args.get(3)) else {
+            println!("Build-view needs a sprite sheet descriptor path and view number!");
+            return
+        };
+        decode::build_view(descriptor_path, number.parse().expect("View number must be a number!"), &options);
+    } else if args.len() >= 3 && args[2] == "repack" {
+        let Some(output_path) = args.get(3) else {
+            println!("Repack needs an output path!");
+            return
+        };
+        decode::repack(&args[1], output_path, &options);
+    } else if args.len() >= 3 && args[2] == "import" {
+        let (Some(import_path), Some(output_path)) = (args.get(3), args.get(4)) else {
+            println!("Import needs a folder of patch files to import, and an output path!");
+            return
+        };
+        decode::import(&args[1], import_path, output_path, &options);
+    } else if args.len() == 2 {
+        decode::decode(&args[1], &options);
     } else {
-        decode::decode(&args[1]);
+        let path = &args[1];
+        let numbers: Vec<usize> = args[3..].iter().map(|n| n.parse().expect("Resource number must be a number!")).collect();
+        let number = numbers.first().copied();
+        match args[2].as_str() {
+            "list" => decode::list(path),
+            "copies" => decode::copies(path),
+            "dump" => decode::dump(path, number),
+            "audit" => if !decode::audit(path) { std::process::exit(1) },
+            "version" => decode::version(path),
+            "manifest" => decode::manifest(path),
+            "disassemble" => decode::disassemble(path, number),
+            "svg" => decode::svg(path, number),
+            "sheet" => decode::sheet(path, number),
+            "composite" => decode::composite(path, &numbers, &options),
+            command => println!("Unknown command: {}", command),
+        }
     }
 }
+
+fn options_from_flags(flags: &[String]) -> decode::Options {
+    let mut options = decode::Options::default();
+    for flag in flags {
+        let (name, value) = flag.split_once('=').unwrap_or((flag, ""));
+        match name {
+            "--dither" => {
+                options.dither = renderer::Dither::from_name(value).expect("Dither must be average, exact or exact-after-scaling!");
+            }
+            "--indexed" => options.indexed = true,
+            "--gif" => options.gif = true,
+            "--transparent" => {
+                options.transparent = Some(value.parse().ok().filter(|&t| t < 16).expect("Transparent must be an EGA colour 0-15!"));
+            }
+            "--ticks" => {
+                options.timing.ticks_per_cel = timing::parse_ticks(value).expect("Ticks must be positive numbers, eg 6 or 4,4,8!");
+                options.timing.is_explicit = true;
+            }
+            "--method" => {
+                options.method = Some(match value {
+                    "none" => resource_reader::METHOD_UNCOMPRESSED,
+                    "lzw" => resource_reader::METHOD_LZW,
+                    "huffman" => resource_reader::METHOD_HUFFMAN,
+                    _ => panic!("Method must be none, lzw or huffman!"),
+                });
+            }
+            "--volume-size" => {
+                options.volume_size = Some(value.parse().expect("Volume size must be a number of bytes!"));
+            }
+            "--threads" => {
+                options.threads = Some(value.parse().ok().filter(|&t| t > 0).expect("Threads must be a positive number!"));
+            }
+            "--loops" => {
+                options.timing.loop_count = value.parse().expect("Loops must be a number, 0 means forever!");
+            }
+            _ => panic!("Unknown option: {}", flag),
+        }
+    }
+    options
+}
@@ -0,0 +1,194 @@
+// This is responsible for a machine-readable inventory of a game's resources, eg for building a catalogue from.
+// It's written as JSON and CSV, with a row per resource, and a content hash to tell versions of a resource apart.
+
+use crate::json;
+use crate::map::{Entry, Map, ResourceType, Source};
+use crate::picture;
+use crate::png;
+use crate::resource_files::Files;
+use crate::resource_reader;
+use crate::view;
+
+pub struct Item {
+    pub resource_type: ResourceType,
+    pub resource_number: usize,
+    pub source: String, // Volume or patch file name, of the copy that was read.
+    pub volume: Option<usize>, // None for patch files. Otherwise where the copy that was read is.
+    pub offset: usize,
+    pub compression: Option<&'static str>, // The rest are None if it can't be read.
+    pub compressed_size: Option<usize>,
+    pub decompressed_size: Option<usize>,
+    pub crc: Option<u32>, // CRC-32 of the decompressed content, as zip tools and dat files use.
+    pub dimensions: Option<(usize, usize)>, // Pictures, and the largest cel of views.
+    pub loops: Option<usize>, // Views only.
+    pub cels: Option<usize>,
+    pub error: Option<String>,
+}
+
+const CSV_HEADINGS: [&str; 14] = [
+    "type", "number", "source", "volume", "offset", "compression", "compressed_size", "decompressed_size",
+    "crc32", "width", "height", "loops", "cels", "error",
+];
+
+pub fn manifest(map: &Map, files: &Files) -> Vec<Item> {
+    map.entries.iter().map(|entry| item(entry, files)).collect()
+}
+
+fn item(entry: &Entry, files: &Files) -> Item {
+    let mut item = Item {
+        resource_type: entry.resource_type,
+        resource_number: entry.resource_number,
+        source: entry.source_name(),
+        volume: if entry.source == Source::Volume { Some(entry.file) } else { None },
+        offset: entry.offset,
+        compression: None,
+        compressed_size: None,
+        decompressed_size: None,
+        crc: None,
+        dimensions: None,
+        loops: None,
+        cels: None,
+        error: None,
+    };
+    // The first copy that reads, like decoding uses, so the location is the one the data came from:
+    let (location, header, data) = match resource_reader::read_first_copy(entry, files) {
+        Ok(read) => read,
+        Err(error) => {
+            item.error = Some(error);
+            return item
+        }
+    };
+    if entry.source == Source::Volume {
+        item.source = location.name();
+        item.volume = Some(location.file);
+    }
+    item.offset = location.offset;
+    item.compression = entry.generation.compression(header.method).map(|c| c.name());
+    item.compressed_size = Some(header.compressed_size);
+    item.decompressed_size = Some(data.len());
+    item.crc = Some(png::crc(&data));
+    match entry.resource_type {
+        ResourceType::Picture => item.dimensions = Some((picture::WIDTH, picture::HEIGHT)),
+        ResourceType::View => match view::View::parse(&data) {
+            Ok(view) => {
+                let cels = view.loops.iter().flat_map(|l| &l.cels);
+                item.dimensions = cels.clone().map(|c| (c.width, c.height)).reduce(|a, b| (a.0.max(b.0), a.1.max(b.1)));
+                item.loops = Some(view.loops.len());
+                item.cels = Some(cels.count());
+            }
+            Err(error) => item.error = Some(format!("can't be parsed: {}", error)),
+        },
+        _ => {}
+    }
+    item
+}
+
+pub fn json(map: &Map, items: &[Item]) -> String {
+    let number = |value: Option<usize>| value.map_or("null".to_string(), |v| v.to_string());
+    let string = |value: Option<&str>| value.map_or("null".to_string(), json::quote);
+    let mut json = String::new();
+    json.push_str("{\n");
+    json.push_str(&format!("  \"interpreter\": {},\n", json::quote(map.generation.name())));
+    json.push_str("  \"resources\": [\n");
+    for (i, item) in items.iter().enumerate() {
+        let fields = [
+            ("type", json::quote(&format!("{:?}", item.resource_type))),
+            ("number", item.resource_number.to_string()),
+            ("source", json::quote(&item.source)),
+            ("volume", number(item.volume)),
+            ("offset", item.offset.to_string()),
+            ("compression", string(item.compression)),
+            ("compressed_size", number(item.compressed_size)),
+            ("decompressed_size", number(item.decompressed_size)),
+            ("crc32", string(item.crc.map(|c| format!("{:08x}", c)).as_deref())),
+            ("width", number(item.dimensions.map(|d| d.0))),
+            ("height", number(item.dimensions.map(|d| d.1))),
+            ("loops", number(item.loops)),
+            ("cels", number(item.cels)),
+            ("error", string(item.error.as_deref())),
+        ];
+        let fields: Vec<String> = fields.iter().map(|(key, value)| format!("\"{}\": {}", key, value)).collect();
+        json.push_str(&format!("    {{ {} }}{}\n", fields.join(", "), if i + 1 < items.len() { "," } else { "" }));
+    }
+    json.push_str("  ]\n}\n");
+    json
+}
+
+// Empty cells where there's no value.
+pub fn csv(items: &[Item]) -> String {
+    let number = |value: Option<usize>| value.map_or(String::new(), |v| v.to_string());
+    let mut csv = CSV_HEADINGS.join(",") + "\n";
+    for item in items {
+        let cells = [
+            format!("{:?}", item.resource_type),
+            item.resource_number.to_string(),
+            quote_csv(&item.source),
+            number(item.volume),
+            item.offset.to_string(),
+            item.compression.unwrap_or_default().to_string(),
+            number(item.compressed_size),
+            number(item.decompressed_size),
+            item.crc.map_or(String::new(), |c| format!("{:08x}", c)),
+            number(item.dimensions.map(|d| d.0)),
+            number(item.dimensions.map(|d| d.1)),
+            number(item.loops),
+            number(item.cels),
+            quote_csv(item.error.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// https://www.rfc-editor.org/rfc/rfc4180
+fn quote_csv(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::sample_game;
+    use crate::resource_source::Memory;
+
+    #[test]
+    fn it_lists_the_sample_game() {
+        let (map, files) = sample_game();
+        let items = manifest(&map, &files);
+        assert_eq!(items.len(), map.entries.len());
+        assert!(items.iter().all(|i| i.error.is_none() && i.crc.is_some()));
+        let view = items.iter().find(|i| i.resource_type == ResourceType::View).unwrap();
+        assert!(view.loops.unwrap() > 0 && view.cels.unwrap() >= view.loops.unwrap() && view.dimensions.is_some());
+        let picture = items.iter().find(|i| i.resource_type == ResourceType::Picture).unwrap();
+        assert_eq!(picture.dimensions, Some((320, 190)));
+
+        let json = json::parse(&json(&map, &items)).unwrap();
+        let resources = json.get("resources").unwrap().as_array().unwrap();
+        assert_eq!(resources.len(), items.len());
+        assert_eq!(resources[0].get("decompressed_size").unwrap().as_f64(), items[0].decompressed_size.map(|s| s as f64));
+        let csv = csv(&items);
+        assert_eq!(csv.lines().count(), items.len() + 1);
+        assert!(csv.lines().all(|l| l.split(',').count() == CSV_HEADINGS.len()));
+    }
+
+    #[test]
+    fn it_records_the_copy_that_was_read() {
+        let mut disks = resource_reader::tests::two_disks(vec![5; 10], vec![5; 10]);
+        let map = Map::read_from(&Memory { files: disks.clone() });
+        disks[1].1.truncate(12); // Disk 1 is a bad dump.
+        let items = manifest(&map, &Files::new(Box::new(Memory { files: disks })));
+        assert_eq!((items[0].source.as_str(), items[0].volume, items[0].offset), ("resource.002", Some(2), 0));
+        assert_eq!(items[0].decompressed_size, Some(10));
+    }
+
+    #[test]
+    fn it_quotes_csv_cells() {
+        assert_eq!(quote_csv("resource.001"), "resource.001");
+        assert_eq!(quote_csv("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+}
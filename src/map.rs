@@ -2,11 +2,15 @@
 // https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/SCI0_resources
 // https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/resource.cpp
 
-use std::collections::HashSet;
+use crate::patch_files;
+use crate::resource_source;
+use crate::version::{self, Generation};
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct Map {
     pub entries: Vec<Entry>,
+    pub generation: Generation,
 }
 
 #[derive(Debug)]
@@ -16,9 +20,24 @@ pub struct Entry {
     pub resource_number: usize,
     pub file: usize,
     pub offset: usize,
+    pub source: Source,
+    pub other_copies: Vec<Location>, // Many common resources are stored on multiple disks, these are the rest.
+    pub generation: Generation, // Decides the volume header layout and what compression methods mean.
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Location {
+    pub file: usize,
+    pub offset: usize,
 }
 
 #[derive(Debug, PartialEq)]
+pub enum Source {
+    Volume, // resource.00x, given by file.
+    Patch(String), // A loose patch file's name, which overrides the volumes. Offset skips its header.
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ResourceType {
 	View,
     Picture,
@@ -47,17 +66,62 @@ pub enum ResourceType {
 
 impl Map {
     pub fn read(path: &str) -> Map {
-        let path = format!("{}/resource.map", path);
-        let data = std::fs::read(path).unwrap();
-        let mut ids: HashSet<usize> = HashSet::new();
+        Map::read_from(resource_source::open(path).as_ref())
+    }
+
+    pub fn read_from(source: &dyn resource_source::Source) -> Map {
+        let mut file = source.open("resource.map").expect("Couldn't open resource.map!");
+        let size = file.size();
+        let data = file.read_at(0, size).expect("Couldn't read resource.map!");
+        let is_sci1_late = version::is_sci1_late_map(&data);
+        let records: Vec<Entry> = if is_sci1_late { Entry::from_sci1_late_map(&data) } else { data.chunks_exact(6).filter_map(Entry::from_data).collect() };
+        let mut indexes: HashMap<usize, usize> = HashMap::new(); // By id.
+        let mut entries: Vec<Entry> = Vec::new();
+        for entry in records {
+            if let Some(&index) = indexes.get(&entry.id) {
+                // Only list a resource once, but remember where the other copies are.
+                let existing = &mut entries[index];
+                let location = Location { file: entry.file, offset: entry.offset };
+                if existing.locations().contains(&location) { continue }
+                existing.other_copies.push(location);
+                continue
+            }
+            indexes.insert(entry.id, entries.len());
+            entries.push(entry);
+        }
+        let generation = if is_sci1_late { Generation::Sci1Late } else { version::sci0_generation(&entries, source) };
+        for entry in &mut entries {
+            entry.generation = generation;
+        }
+
+        // Loose patch files take precedence over the volumes:
+        for patch in Map::read_patches(source).entries {
+            match entries.iter_mut().find(|e| e.id == patch.id) {
+                Some(existing) => *existing = patch,
+                None => entries.push(patch),
+            }
+        }
+        Map { entries, generation }
+    }
+
+    // Just the patch files, eg for importing from a folder without a resource.map.
+    pub fn read_patches(source: &dyn resource_source::Source) -> Map {
         let mut entries: Vec<Entry> = Vec::new();
-        for chunk in data.chunks_exact(6) {
-            let Some(entry) = Entry::from_data(chunk) else { continue };
-            if ids.contains(&entry.id) { continue } // Since many common resources are stored on multiple disks, only add them once.
-            ids.insert(entry.id);
+        for patch in patch_files::find(source) {
+            let id = (patch.type_number << 11) | patch.resource_number;
+            let entry = Entry {
+                id,
+                resource_type: ResourceType::from(patch.type_number),
+                resource_number: patch.resource_number,
+                file: 0,
+                offset: patch.data_offset,
+                source: Source::Patch(patch.name),
+                other_copies: Vec::new(),
+                generation: Generation::Sci0Late, // Patches aren't compressed, so it doesn't matter.
+            };
             entries.push(entry);
         }
-        Map { entries }
+        Map { entries, generation: Generation::Sci0Late }
     }
 }
 
@@ -78,12 +142,65 @@ impl Entry {
             resource_number,
             file,
             offset,
+            source: Source::Volume,
+            other_copies: Vec::new(),
+            generation: Generation::Sci0Late,
         })
     }
+
+    // Late SCI1 maps have an index of where each type's entries start, then entries of the number and location.
+    // https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/resource.cpp (readResourceMapSCI1)
+    fn from_sci1_late_map(data: &[u8]) -> Vec<Entry> {
+        let index: Vec<&[u8]> = data.chunks_exact(3).take_while(|r| r[0] != 0xff).collect();
+        let mut entries: Vec<Entry> = Vec::new();
+        for (i, record) in index.iter().enumerate() {
+            let type_number = (record[0] & 0x7f) as usize;
+            let start = (record[1] as usize) + ((record[2] as usize) << 8);
+            let next = data[(i + 1) * 3..].get(1..3).expect("Late SCI1 map index is truncated!");
+            let end = (next[0] as usize) + ((next[1] as usize) << 8);
+            for chunk in data[start..end].chunks_exact(6) {
+                let resource_number = (chunk[0] as usize) + ((chunk[1] as usize) << 8);
+                let rest = (chunk[2] as usize) + ((chunk[3] as usize) << 8) +
+                    ((chunk[4] as usize) << 16) + ((chunk[5] as usize) << 24);
+                entries.push(Entry {
+                    id: (type_number << 11) | resource_number,
+                    resource_type: ResourceType::from(type_number),
+                    resource_number,
+                    file: rest >> 28, // High 4 bits.
+                    offset: rest & 0xfffffff, // Low 28 bits.
+                    source: Source::Volume,
+                    other_copies: Vec::new(),
+                    generation: Generation::Sci1Late,
+                });
+            }
+        }
+        entries
+    }
+
+    // Every copy, the first being the one to use.
+    pub fn locations(&self) -> Vec<Location> {
+        let mut locations = vec![Location { file: self.file, offset: self.offset }];
+        locations.extend(&self.other_copies);
+        locations
+    }
+
+    // Where the resource came from, for listings.
+    pub fn source_name(&self) -> String {
+        match &self.source {
+            Source::Volume => Location { file: self.file, offset: self.offset }.name(),
+            Source::Patch(name) => name.clone(),
+        }
+    }
+}
+
+impl Location {
+    pub fn name(&self) -> String {
+        format!("resource.{:03}", self.file)
+    }
 }
 
 impl ResourceType {
-    fn from(value: usize) -> ResourceType {
+    pub fn from(value: usize) -> ResourceType {
         match value {
             0 => ResourceType::View,
             1 => ResourceType::Picture,
@@ -110,4 +227,79 @@ impl ResourceType {
             _ => ResourceType::Unknown,
         }
     }
+
+    // The inverse of from, for writing maps.
+    pub fn number(&self) -> usize {
+        match self {
+            ResourceType::View => 0,
+            ResourceType::Picture => 1,
+            ResourceType::Script => 2,
+            ResourceType::Text => 3,
+            ResourceType::Sound => 4,
+            ResourceType::Memory => 5,
+            ResourceType::Vocab => 6,
+            ResourceType::Font => 7,
+            ResourceType::Cursor => 8,
+            ResourceType::Patch => 9,
+            ResourceType::Bitmap => 10,
+            ResourceType::Palette => 11,
+            ResourceType::CdAudio => 12,
+            ResourceType::Audio => 13,
+            ResourceType::Sync => 14,
+            ResourceType::Message => 15,
+            ResourceType::Map => 16,
+            ResourceType::Heap => 17,
+            ResourceType::Audio36 => 18,
+            ResourceType::Sync36 => 19,
+            ResourceType::Translation => 20,
+            ResourceType::Rave => 21,
+            ResourceType::Unknown => panic!("Unknown resource types don't have a number!"),
+        }
+    }
+
+    // Names used for patch files, as SCI0 names them (view.123), then as later games do (123.v56).
+    // https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/resource.cpp
+    pub fn patch_names(&self) -> (&'static str, &'static str) {
+        match self {
+            ResourceType::View => ("view", "v56"),
+            ResourceType::Picture => ("pic", "p56"),
+            ResourceType::Script => ("script", "scr"),
+            ResourceType::Text => ("text", "tex"),
+            ResourceType::Sound => ("sound", "snd"),
+            ResourceType::Memory => ("memory", "mem"),
+            ResourceType::Vocab => ("vocab", "voc"),
+            ResourceType::Font => ("font", "fon"),
+            ResourceType::Cursor => ("cursor", "cur"),
+            ResourceType::Patch => ("patch", "pat"),
+            ResourceType::Bitmap => ("bitmap", "bit"),
+            ResourceType::Palette => ("palette", "pal"),
+            ResourceType::CdAudio => ("cdaudio", "cda"),
+            ResourceType::Audio => ("audio", "aud"),
+            ResourceType::Sync => ("sync", "syn"),
+            ResourceType::Message => ("message", "msg"),
+            ResourceType::Map => ("map", "map"),
+            ResourceType::Heap => ("heap", "hep"),
+            ResourceType::Audio36 => ("audio36", "aud36"),
+            ResourceType::Sync36 => ("sync36", "syn36"),
+            ResourceType::Translation => ("xlate", "trn"),
+            ResourceType::Rave => ("rave", "rav"),
+            ResourceType::Unknown => ("unknown", "unk"),
+        }
+    }
+
+    // Accepts either name, eg view, v56 or pic, p56.
+    pub fn from_patch_name(name: &str) -> Option<ResourceType> {
+        (0..22).map(ResourceType::from).find(|t| {
+            let (long, short) = t.patch_names();
+            name == long || name == short
+        })
+    }
+
+    // Also accepts typeNN, which is how types without a name are dumped.
+    pub fn number_from_patch_name(name: &str) -> Option<usize> {
+        if let Some(number) = name.strip_prefix("type") {
+            return number.parse().ok().filter(|&n| n < 32) // Types are 5 bits.
+        }
+        ResourceType::from_patch_name(name).map(|t| t.number())
+    }
 }
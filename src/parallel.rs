@@ -0,0 +1,61 @@
+// This is responsible for spreading slow jobs, like rendering resources, across threads.
+// Results are handed back in the order of the jobs, so progress is reported the same way however many threads there are.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+// One per core, unless told otherwise.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// Runs the job for each item on a pool of threads, which take the next item as they become free.
+// Each result is given to done in the items' order, once it and all the ones before it have finished.
+// If a job panics, the panic is passed on once the rest have finished.
+pub fn for_each<T: Sync, R: Send>(items: &[T], threads: usize, job: impl Fn(&T) -> R + Sync, mut done: impl FnMut(R)) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(usize, R)>();
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (next, job) = (&next, &job);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else { break };
+                sender.send((index, job(item))).unwrap();
+            });
+        }
+        drop(sender); // So receiving ends when the workers do.
+
+        // Hold on to results that finish early until it's their turn:
+        let mut finished: Vec<Option<R>> = items.iter().map(|_| None).collect();
+        let mut reported = 0;
+        for (index, result) in receiver {
+            finished[index] = Some(result);
+            while let Some(result) = finished.get_mut(reported).and_then(|r| r.take()) {
+                done(result);
+                reported += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reports_results_in_order() {
+        let items: Vec<u64> = (0..20).collect();
+        for threads in [1, 4, 50] {
+            let mut results: Vec<u64> = Vec::new();
+            // Earlier items take longer, so they finish out of order.
+            for_each(&items, threads, |&i| {
+                std::thread::sleep(std::time::Duration::from_millis(20 - i));
+                i * 2
+            }, |r| results.push(r));
+            assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<u64>>());
+        }
+        for_each(&[] as &[u64], 4, |&i| i, |_| panic!("There's nothing to do!"));
+    }
+}
@@ -0,0 +1,148 @@
+// This is responsible for finding loose patch files in the game folder, eg view.123, pic.045 or 123.pic,
+// which the interpreter loads in preference to the copy in the volumes. Fan translations and official patches use these.
+// Each starts with the resource type (with the high bit set) and the length of any extra header, then the uncompressed resource.
+// https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/SCI0_resources
+// https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/resource_patcher.cpp
+
+use crate::map::ResourceType;
+use crate::resource_source::Source;
+
+pub struct Patch {
+    pub name: String, // File name within the game folder.
+    pub type_number: usize, // Rather than the ResourceType, so unknown types are kept.
+    pub resource_number: usize,
+    pub data_offset: usize, // Where the resource starts after the patch header.
+}
+
+// Sorted by name so the results don't depend on the filesystem.
+pub fn find(source: &dyn Source) -> Vec<Patch> {
+    let mut names = source.names();
+    names.sort();
+    let mut patches: Vec<Patch> = Vec::new();
+    for name in names {
+        let Some((type_number, resource_number)) = parse_name(&name) else { continue };
+        let Some((header_type, data_offset)) = read_header(source, &name) else {
+            println!("Ignoring patch file that's too short: {}", name);
+            continue
+        };
+        if header_type != type_number {
+            println!("Ignoring patch file whose header says it's a {:?}: {}", ResourceType::from(header_type), name);
+            continue
+        }
+        patches.push(Patch { name, type_number, resource_number, data_offset });
+    }
+    patches
+}
+
+// Makes a patch file, with no extra header.
+pub fn patch_data(type_number: usize, resource: &[u8]) -> Vec<u8> {
+    let mut patch: Vec<u8> = vec![0x80 | type_number as u8, 0]; // Resource type with the high bit set, then the header length.
+    patch.extend(resource);
+    patch
+}
+
+// The name SCI0 interpreters look for, eg view.123.
+pub fn name(type_number: usize, resource_number: usize) -> String {
+    let resource_type = ResourceType::from(type_number);
+    if resource_type == ResourceType::Unknown {
+        return format!("type{}.{:03}", type_number, resource_number)
+    }
+    format!("{}.{:03}", resource_type.patch_names().0, resource_number)
+}
+
+// Either type.number as SCI0 uses, or number.type as later games do. Gives the type number and resource number.
+pub fn parse_name(name: &str) -> Option<(usize, usize)> {
+    let (left, right) = name.to_lowercase().split_once('.').map(|(l, r)| (l.to_string(), r.to_string()))?;
+    let (type_name, number) = if let Ok(number) = right.parse::<usize>() { (left, number) } else { (right, left.parse().ok()?) };
+    if number > 0b111_11111111 { return None }
+    let type_number = ResourceType::number_from_patch_name(&type_name)?;
+    Some((type_number, number))
+}
+
+// Only reads the start of the file, the rest is read when the resource is wanted.
+fn read_header(source: &dyn Source, name: &str) -> Option<(usize, usize)> {
+    let mut file = source.open(name)?;
+    let size = file.size();
+    parse_header(&file.read_at(0, size.min(2))?, size)
+}
+
+// Returns the type number, and the offset of the resource.
+fn parse_header(data: &[u8], size: usize) -> Option<(usize, usize)> {
+    if data.len() < 2 { return None }
+    let type_number = (data[0] & 0x7f) as usize;
+    let header_length = data[1] as usize;
+    // Some later patches flag a well-known header size with the high bit:
+    let header_length = if header_length & 0x80 == 0 { header_length } else {
+        match header_length & 0x7f {
+            0 => 24,
+            1 => 2,
+            4 => 8,
+            _ => return None,
+        }
+    };
+    let data_offset = 2 + header_length;
+    if data_offset > size { return None }
+    Some((type_number, data_offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{self, Source};
+    use crate::resource_files;
+    use crate::resource_reader::{self, tests::{TempDir, SAMPLE_PATH}};
+
+    #[test]
+    fn it_parses_names() {
+        assert_eq!(parse_name("view.123"), Some((ResourceType::View.number(), 123)));
+        assert_eq!(parse_name("PIC.045"), Some((ResourceType::Picture.number(), 45)));
+        assert_eq!(parse_name("123.pic"), Some((ResourceType::Picture.number(), 123)));
+        assert_eq!(parse_name("10.scr"), Some((ResourceType::Script.number(), 10)));
+        assert_eq!(parse_name("type30.001"), Some((30, 1)));
+        assert_eq!(parse_name("type32.001"), None);
+        assert_eq!(parse_name("resource.001"), None);
+        assert_eq!(parse_name("resource.map"), None);
+        assert_eq!(parse_name("Readme.md"), None);
+    }
+
+    #[test]
+    fn it_names_patches() {
+        assert_eq!(name(0, 5), "view.005");
+        assert_eq!(name(2, 123), "script.123");
+        assert_eq!(name(30, 1), "type30.001");
+        assert_eq!(parse_name(&name(30, 1)), Some((30, 1)));
+        assert_eq!(parse_header(&patch_data(1, &[7, 8]), 4), Some((ResourceType::Picture.number(), 2)));
+    }
+
+    #[test]
+    fn it_parses_headers() {
+        assert_eq!(parse_header(&[0x81, 0], 4), Some((1, 2)));
+        assert_eq!(parse_header(&[0x80, 2], 5), Some((0, 4)));
+        assert_eq!(parse_header(&[0x80, 3], 3), None);
+    }
+
+    #[test]
+    fn it_overrides_volume_resources() {
+        let dir = TempDir::new("patches");
+        let path = dir.path.as_str();
+        for name in ["resource.map", "resource.001"] {
+            std::fs::copy(format!("{}/{}", SAMPLE_PATH, name), dir.join(name)).unwrap();
+        }
+        let original_map = map::Map::read(path);
+        let first_picture = original_map.entries.iter().find(|e| e.resource_type == ResourceType::Picture).unwrap();
+        let replacement = vec![1, 2, 3];
+        let mut patch: Vec<u8> = vec![0x81, 1, 0xff]; // One byte of extra header to skip.
+        patch.extend(&replacement);
+        std::fs::write(format!("{}/pic.{:03}", path, first_picture.resource_number), &patch).unwrap();
+        std::fs::write(format!("{}/1999.pic", path), &patch).unwrap();
+
+        let patched_map = map::Map::read(path);
+        let files = resource_files::Files::open(path);
+        assert_eq!(patched_map.entries.len(), original_map.entries.len() + 1);
+        let patched = patched_map.entries.iter().find(|e| e.id == first_picture.id).unwrap();
+        assert_eq!(patched.source, Source::Patch(format!("pic.{:03}", first_picture.resource_number)));
+        assert_eq!(resource_reader::read(patched, &files), replacement);
+        let added = patched_map.entries.last().unwrap();
+        assert_eq!((added.resource_type, added.resource_number, added.source_name()), (ResourceType::Picture, 1999, "1999.pic".to_string()));
+    }
+}
@@ -9,7 +9,11 @@ use crate::picture_splitter;
 pub const WIDTH: usize = 320;
 pub const HEIGHT: usize = 190;
 
-pub const DEFAULT_PALETTE: [u8; 40] = [
+// There are four palettes one after another, so colour values 0-159 pick an entry from one of them.
+pub const PALETTE_SIZE: usize = 40;
+pub const PALETTE_COUNT: usize = 4;
+
+pub const DEFAULT_PALETTE: [u8; PALETTE_SIZE] = [
     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
     0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x88,
     0x88, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x88,
@@ -17,58 +21,132 @@ pub const DEFAULT_PALETTE: [u8; 40] = [
     0x08, 0x91, 0x2a, 0x3b, 0x4c, 0x5d, 0x6e, 0x88,
 ];
 
+// The visual, priority and control screens that pictures are drawn on.
+// Priority and control are plain 0-15 values, only visual uses dither doubles.
 pub struct Picture {
     pub picture: Vec<u8>, // Unlike cels, pictures support dithering, so two colour indices are stored in each nibble of the u8.
+    pub priority: Vec<u8>,
+    pub control: Vec<u8>,
 }
 
 impl Picture {
-    pub fn parse(data: &[u8]) -> Picture {
-        let actions = picture_splitter::split(data);
-        let picture = draw(&actions);
-        Picture { picture }
+    pub fn parse(data: &[u8]) -> Result<Picture, String> {
+        let mut picture = Picture::blank();
+        picture.draw_over(data)?;
+        Ok(picture)
+    }
+
+    // Draws each picture resource on top of the previous ones, for rooms that overlay pictures.
+    pub fn composite(resources: &[Vec<u8>]) -> Result<Picture, String> {
+        let mut picture = Picture::blank();
+        for data in resources {
+            picture.draw_over(data)?;
+        }
+        Ok(picture)
+    }
+
+    pub fn blank() -> Picture {
+        Picture {
+            picture: vec![BACKGROUND; WIDTH * HEIGHT],
+            priority: vec![0; WIDTH * HEIGHT],
+            control: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    // Like the interpreter's DrawPic without clearing the screen first: the screens are kept, but the palette and pens start afresh.
+    pub fn draw_over(&mut self, data: &[u8]) -> Result<(), String> {
+        let actions = picture_splitter::split(data)?;
+        let mut painter = Painter::on(self);
+        for a in &actions {
+            painter.apply(a);
+        }
+        Ok(())
     }
 }
 
-fn draw(actions: &[picture_splitter::ActionArguments]) -> Vec<u8> {
-    let background = dither_double_from_colours(palette::WHITE, palette::WHITE);
-    let mut picture: Vec<u8> = vec![background; WIDTH * HEIGHT];
-    let mut is_drawing = false;
-    let mut colour = background;
-    let mut is_rectangle = false;
-    let mut is_pattern = false;
-    let mut pattern_size: u8 = 0;
-    let mut palette = DEFAULT_PALETTE.to_vec();
-    for a in actions {
+// Visual starts white, and fills only spread over white.
+const BACKGROUND: u8 = dither_double_from_colours(palette::WHITE, palette::WHITE);
+
+// Holds the drawing state, so actions can be applied one at a time.
+pub struct Painter<'a> {
+    pub canvas: &'a mut Picture,
+    pub is_drawing: bool,
+    pub colour: u8,
+    pub is_drawing_priority: bool,
+    pub priority_colour: u8,
+    pub is_drawing_control: bool,
+    pub control_colour: u8,
+    pub is_rectangle: bool,
+    pub is_pattern: bool,
+    pub pattern_size: u8,
+    pub palettes: [[u8; PALETTE_SIZE]; PALETTE_COUNT],
+    pub background: u8,
+}
+
+impl<'a> Painter<'a> {
+    pub fn on(canvas: &'a mut Picture) -> Painter<'a> {
+        Painter {
+            canvas,
+            is_drawing: false,
+            colour: BACKGROUND,
+            is_drawing_priority: false,
+            priority_colour: 0,
+            is_drawing_control: false,
+            control_colour: 0,
+            is_rectangle: false,
+            is_pattern: false,
+            pattern_size: 0,
+            palettes: [DEFAULT_PALETTE; PALETTE_COUNT],
+            background: BACKGROUND,
+        }
+    }
+
+    // The screens currently being drawn on, as (screen, colour, blank colour).
+    // The first is the one that decides where fills can spread, as in the interpreter.
+    fn planes(&mut self) -> Vec<(&mut [u8], u8, u8)> {
+        let Picture { picture, priority, control } = &mut *self.canvas;
+        let mut planes: Vec<(&mut [u8], u8, u8)> = Vec::new();
+        if self.is_drawing {
+            planes.push((picture, self.colour, self.background));
+        }
+        if self.is_drawing_priority {
+            planes.push((priority, self.priority_colour, 0));
+        }
+        if self.is_drawing_control {
+            planes.push((control, self.control_colour, 0));
+        }
+        planes
+    }
+
+    pub fn apply(&mut self, a: &picture_splitter::ActionArguments) {
         match a.action {
             // Colour:
             picture_splitter::Action::SetVisualColour => {
-                is_drawing = true;
-                if a.arguments.is_empty() { continue }
+                self.is_drawing = true;
+                if a.arguments.is_empty() { return }
                 let value = a.arguments[0] as usize;
-                let index = value % palette.len();
-                let palette_number = value / palette.len();
-                if palette_number > 0 {
-                    panic!("Multiple palettes not supported!"); // PQ2, SQ3, LSL2+3, all don't use secondary palettes.
+                // Values past the last palette don't change the colour.
+                if let Some(palette) = self.palettes.get(value / PALETTE_SIZE) {
+                    self.colour = palette[value % PALETTE_SIZE];
                 }
-                colour = palette[index];
             }
             picture_splitter::Action::DisableVisual => {
-                is_drawing = false;
+                self.is_drawing = false;
             }
             // Lines:
             picture_splitter::Action::LongLines => {
-                if is_drawing {
-                    draw_long_lines(&mut picture, colour, &a.arguments);
+                for (plane, colour, _) in self.planes() {
+                    draw_long_lines(plane, colour, &a.arguments);
                 }
             }
             picture_splitter::Action::MediumRelativeLines => {
-                if is_drawing {
-                    draw_medium_relative_lines(&mut picture, colour, &a.arguments);
+                for (plane, colour, _) in self.planes() {
+                    draw_medium_relative_lines(plane, colour, &a.arguments);
                 }
             }
             picture_splitter::Action::ShortRelativeLines => {
-                if is_drawing {
-                    draw_short_relative_lines(&mut picture, colour, &a.arguments);
+                for (plane, colour, _) in self.planes() {
+                    draw_short_relative_lines(plane, colour, &a.arguments);
                 }
             }            
             // Patterns:
@@ -79,88 +157,52 @@ fn draw(actions: &[picture_splitter::ActionArguments]) -> Vec<u8> {
                     if a.arguments.len() > 1 {
                         //println!("SetPattern has extra arguments, len: {}!", a.arguments.len());
                     }
-                    is_rectangle = a.arguments[0] & 0x10 != 0; // vs circle.
-                    is_pattern = a.arguments[0] & 0x20 != 0; // vs solid.
-                    pattern_size = a.arguments[0] & 7; // 0-7.
+                    self.is_rectangle = a.arguments[0] & 0x10 != 0; // vs circle.
+                    self.is_pattern = a.arguments[0] & 0x20 != 0; // vs solid.
+                    self.pattern_size = a.arguments[0] & 7; // 0-7.
                     //println!("SetPattern: {:02x} rect {}, pattern {}, size {}", a.arguments[0], is_rectangle, is_pattern, pattern_size);
                 }
             }
             picture_splitter::Action::LongPatterns => {
-                if is_drawing {
-                    let chunk_size = if is_pattern { 4 } else { 3 };
-                    for chunk in a.arguments.chunks_exact(chunk_size) {
-                        let pattern_number = if is_pattern { chunk[0] } else { 0 };
-                        let after_pattern_number = if is_pattern { &chunk[1..] } else { chunk };
-                        let (x, y) = xy_from_triple(after_pattern_number);
-                        draw_pattern(&mut picture, colour, x, y, pattern_number as usize, pattern_size as usize, is_pattern, is_rectangle);
-                    }
+                let (pattern_size, is_pattern, is_rectangle) = (self.pattern_size as usize, self.is_pattern, self.is_rectangle);
+                for (plane, colour, _) in self.planes() {
+                    draw_long_patterns(plane, &a.arguments, colour, pattern_size, is_pattern, is_rectangle);
                 }
             }
             picture_splitter::Action::MediumRelativePatterns => {
-                if is_drawing {
-                    if a.arguments.is_empty() { continue }
-                    // Pattern number byte is only there if is_pattern is set:
-                    let pattern_number = if is_pattern { a.arguments[0] } else { 0 };
-                    let after_pattern_number = if is_pattern { &a.arguments[1..] } else { &a.arguments };
-                    // Starting position:
-                    if after_pattern_number.len() < 3 { continue }
-                    let (mut x, mut y) = xy_from_triple(after_pattern_number);
-                    draw_pattern(&mut picture, colour, x, y, pattern_number as usize, pattern_size as usize, is_pattern, is_rectangle);
-                    // Remaining ones that are deltas:
-                    let remaining_arguments = &after_pattern_number[3..];
-                    let chunk_size = if is_pattern { 3 } else { 2 };
-                    let chunks = remaining_arguments.chunks_exact(chunk_size);
-                    for chunk in chunks {
-                        let pattern_number = if is_pattern { chunk[0] } else { 0 };
-                        let after_pattern_number = if is_pattern { &chunk[1..] } else { chunk };
-                        // Y uses sign-magnitude:
-                        let y_raw = (after_pattern_number[0] & 0x7f) as usize; 
-                        let y_is_minus = after_pattern_number[0] & 0x80 > 0;
-                        if y_is_minus && y_raw > y {
-                            //println!("Medium relative pattern going into negative Y! {} - {}", y, y_raw);
-                            continue
-                        }
-                        y = if y_is_minus { y - y_raw } else { y + y_raw };
-                        // X uses 2s complement:
-                        let x_delta = after_pattern_number[1] as i8;
-                        x = ((x as isize) + (x_delta as isize)) as usize;
-                        draw_pattern(&mut picture, colour, x, y, pattern_number as usize, pattern_size as usize, is_pattern, is_rectangle);
-                    }
+                let (pattern_size, is_pattern, is_rectangle) = (self.pattern_size as usize, self.is_pattern, self.is_rectangle);
+                for (plane, colour, _) in self.planes() {
+                    draw_medium_relative_patterns(plane, &a.arguments, colour, pattern_size, is_pattern, is_rectangle);
                 }
             }
             picture_splitter::Action::ShortRelativePatterns => {
-                if is_drawing {
-                    draw_short_relative_patterns(&mut picture, &a.arguments, colour, pattern_size as usize, is_pattern, is_rectangle);
-                }                
+                let (pattern_size, is_pattern, is_rectangle) = (self.pattern_size as usize, self.is_pattern, self.is_rectangle);
+                for (plane, colour, _) in self.planes() {
+                    draw_short_relative_patterns(plane, &a.arguments, colour, pattern_size, is_pattern, is_rectangle);
+                }
             }
             // Etc:
             picture_splitter::Action::FloodFill => {
-                if is_drawing {
-                    fill(&mut picture, colour, &a.arguments, background);
-                }
+                fill(&mut self.planes(), &a.arguments);
             }
             picture_splitter::Action::CommandExtensions => {
-                if a.arguments.is_empty() { continue }
+                if a.arguments.is_empty() { return }
                 let command = a.arguments[0];
                 let ext_args = &a.arguments[1..];
                 match command {
                     0 => { // Set palette entry.
                         for chunk in ext_args.chunks_exact(2) {
                             let index_and_palette = chunk[0] as usize;
-                            let new_colour = chunk[1];
-                            let index = index_and_palette % palette.len();
-                            let palette_number = index_and_palette / palette.len();
-                            if palette_number > 0 {
-                                panic!("Multiple palettes not supported!"); // PQ2, SQ3, LSL2+3, all don't use secondary palettes.
+                            if let Some(palette) = self.palettes.get_mut(index_and_palette / PALETTE_SIZE) {
+                                palette[index_and_palette % PALETTE_SIZE] = chunk[1];
                             }
-                            palette[index] = new_colour;
                         }
                     }
                     1 => { // Set entire palette.
-                        let palette_number = ext_args[0];
-                        if palette_number == 0 { // Ignore non-primary palettes.
+                        let palette_number = ext_args[0] as usize;
+                        if let Some(palette) = self.palettes.get_mut(palette_number) {
                             let new_palette = &ext_args[1..];
-                            for (i, new_colour) in new_palette.iter().enumerate() {
+                            for (i, new_colour) in new_palette.iter().enumerate().take(PALETTE_SIZE) {
                                 palette[i] = *new_colour;
                             }
                         }
@@ -168,22 +210,76 @@ fn draw(actions: &[picture_splitter::ActionArguments]) -> Vec<u8> {
                     _ => {} // Ignore monochrome / sci01 stuff.
                 }                
             }
-            // Unused:
-            picture_splitter::Action::SetPriorityColour => {}
-            picture_splitter::Action::DisablePriority => {}
-            picture_splitter::Action::SetControlColour => {}
-            picture_splitter::Action::DisableControl => {}
+            // Priority and control:
+            picture_splitter::Action::SetPriorityColour => {
+                self.is_drawing_priority = true;
+                if let Some(value) = a.arguments.first() {
+                    self.priority_colour = value & 0xf;
+                }
+            }
+            picture_splitter::Action::DisablePriority => {
+                self.is_drawing_priority = false;
+            }
+            picture_splitter::Action::SetControlColour => {
+                self.is_drawing_control = true;
+                if let Some(value) = a.arguments.first() {
+                    self.control_colour = value & 0xf;
+                }
+            }
+            picture_splitter::Action::DisableControl => {
+                self.is_drawing_control = false;
+            }
             picture_splitter::Action::End => {} // Done!
         }
     }
-    picture
 }
 
 // To support the dithering, this converts two 0-15 colours into a 'dither double'.
-fn dither_double_from_colours(a: u8, b: u8) -> u8 {
+const fn dither_double_from_colours(a: u8, b: u8) -> u8 {
     (a << 4) + b
 }
 
+fn draw_long_patterns(picture: &mut [u8], arguments: &[u8], colour: u8, pattern_size: usize, is_pattern: bool, is_rectangle: bool) {
+    let chunk_size = if is_pattern { 4 } else { 3 };
+    for chunk in arguments.chunks_exact(chunk_size) {
+        let pattern_number = if is_pattern { chunk[0] } else { 0 };
+        let after_pattern_number = if is_pattern { &chunk[1..] } else { chunk };
+        let (x, y) = xy_from_triple(after_pattern_number);
+        draw_pattern(picture, colour, x, y, pattern_number as usize, pattern_size, is_pattern, is_rectangle);
+    }
+}
+
+fn draw_medium_relative_patterns(picture: &mut [u8], arguments: &[u8], colour: u8, pattern_size: usize, is_pattern: bool, is_rectangle: bool) {
+    if arguments.is_empty() { return }
+    // Pattern number byte is only there if is_pattern is set:
+    let pattern_number = if is_pattern { arguments[0] } else { 0 };
+    let after_pattern_number = if is_pattern { &arguments[1..] } else { arguments };
+    // Starting position:
+    if after_pattern_number.len() < 3 { return }
+    let (mut x, mut y) = xy_from_triple(after_pattern_number);
+    draw_pattern(picture, colour, x, y, pattern_number as usize, pattern_size, is_pattern, is_rectangle);
+    // Remaining ones that are deltas:
+    let remaining_arguments = &after_pattern_number[3..];
+    let chunk_size = if is_pattern { 3 } else { 2 };
+    let chunks = remaining_arguments.chunks_exact(chunk_size);
+    for chunk in chunks {
+        let pattern_number = if is_pattern { chunk[0] } else { 0 };
+        let after_pattern_number = if is_pattern { &chunk[1..] } else { chunk };
+        // Y uses sign-magnitude:
+        let y_raw = (after_pattern_number[0] & 0x7f) as usize; 
+        let y_is_minus = after_pattern_number[0] & 0x80 > 0;
+        if y_is_minus && y_raw > y {
+            //println!("Medium relative pattern going into negative Y! {} - {}", y, y_raw);
+            continue
+        }
+        y = if y_is_minus { y - y_raw } else { y + y_raw };
+        // X uses 2s complement:
+        let x_delta = after_pattern_number[1] as i8;
+        x = ((x as isize) + (x_delta as isize)) as usize;
+        draw_pattern(picture, colour, x, y, pattern_number as usize, pattern_size, is_pattern, is_rectangle);
+    }
+}
+
 fn draw_short_relative_patterns(picture: &mut [u8], arguments: &[u8], colour: u8, pattern_size: usize, is_pattern: bool, is_rectangle: bool) {
     if arguments.is_empty() { return }
     // Pattern number byte is only there if is_pattern is set:
@@ -226,7 +322,7 @@ fn draw_short_relative_patterns(picture: &mut [u8], arguments: &[u8], colour: u8
 
 // Hard to find SCI specs, so i'm assuming this is much like AGI:
 // https://www.agidev.com/articles/agispec/agispecs-7.html
-fn draw_pattern(picture: &mut [u8], colour: u8, x: usize, y: usize, pattern_number: usize, pattern_size: usize, is_pattern: bool, is_rectangle: bool) {
+pub fn draw_pattern(picture: &mut [u8], colour: u8, x: usize, y: usize, pattern_number: usize, pattern_size: usize, is_pattern: bool, is_rectangle: bool) {
     let circle_0: Vec<&str> = vec![
         "X",
     ];
@@ -394,7 +490,7 @@ impl<'a> LoopingPatternBitStream<'a> {
 }
 
 // Converts 3 bytes XY,XX,YY to x,y.
-fn xy_from_triple(data: &[u8]) -> (usize, usize) {
+pub fn xy_from_triple(data: &[u8]) -> (usize, usize) {
     let x = (((data[0] >> 4) as usize) << 8) + (data[1] as usize);
     let y = (((data[0] & 0xf) as usize) << 8) + (data[2] as usize);
     (x, y)
@@ -465,10 +561,11 @@ fn draw_short_relative_lines(picture: &mut [u8], colour: u8, arguments: &[u8]) {
     }
 }
 
-// Fills all background-coloured pixels with colour.
-fn fill(picture: &mut [u8], colour: u8, arguments: &[u8], background: u8) {
-    if colour == background { return } // White is the boundary, so this would just end up in an infinite loop.
-    // println!("Fill args: {}", arguments.len());
+// Fills all blank pixels of the first plane, and colours the same pixels in the other planes.
+// Eg white is the boundary when drawing visual, but black is when only drawing priority.
+fn fill(planes: &mut [(&mut [u8], u8, u8)], arguments: &[u8]) {
+    let Some(&(_, colour, blank)) = planes.first() else { return };
+    if colour == blank { return } // This would just end up in an infinite loop.
     for chunk in arguments.chunks_exact(3) {
         let (x, y) = xy_from_triple(chunk);
         if x >= WIDTH || y >= HEIGHT {
@@ -480,15 +577,16 @@ fn fill(picture: &mut [u8], colour: u8, arguments: &[u8], background: u8) {
             let x = xy.0; 
             let y = xy.1; 
             let offset = y * WIDTH + x;
-            if picture[offset] != background { continue }
-            picture[offset] = colour;
+            if planes[0].0[offset] != blank { continue }
+            for (plane, colour, _) in planes.iter_mut() {
+                plane[offset] = *colour;
+            }
             if x > 0 { queue.push((x-1, y)); } // Left.
             if x < WIDTH-1 { queue.push((x+1, y)); } // Right.
             if y > 0 { queue.push((x, y-1)); } // Up.
             if y < HEIGHT-1 { queue.push((x, y+1)); } // Down.
         }
     }
-    //println!("Done fill");
 }
 
 fn draw_sierra_line(picture: &mut [u8], colour: u8, x1: usize, y1: usize, x2: usize, y2: usize) {
@@ -564,7 +662,7 @@ fn draw_sierra_line(picture: &mut [u8], colour: u8, x1: usize, y1: usize, x2: us
     picture[y2 * WIDTH + x2] = colour;
 }
 
-fn describe_buf(buf: &[u8]) -> String {
+pub fn describe_buf(buf: &[u8]) -> String {
     let mut s = format!("Len: ({}) [", buf.len());
     for (i, x) in buf.iter().enumerate() {
         if i != 0 {
@@ -574,4 +672,42 @@ fn describe_buf(buf: &[u8]) -> String {
     }
     s.push(']');
     s
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A closed box from (2, 2) to (8, 8) as long lines.
+    const BOX: [u8; 16] = [0xf6, 0, 2, 2, 0, 8, 2, 0, 8, 8, 0, 2, 8, 0, 2, 2];
+
+    fn pixels(plane: &[u8], y: usize, xs: std::ops::Range<usize>) -> Vec<u8> {
+        plane[(y * WIDTH + xs.start)..(y * WIDTH + xs.end)].to_vec()
+    }
+
+    #[test]
+    fn it_draws_overlays_over_what_is_already_there() {
+        let base = [&[0xfe, 0, 1, 0x22, 0xf0, 1][..], &BOX, &[0xff]].concat(); // Palette entry 1 changed to colour 2.
+        let overlay = [0xf0, 1, 0xf8, 0, 5, 5, 0xff]; // Fills within the base's box, in the default palette's colour 1.
+        let without_pen = [0xf6, 0, 20, 20, 0, 30, 20, 0xff]; // Pens start afresh, so this draws nothing.
+        let picture = Picture::composite(&[base, overlay.to_vec(), without_pen.to_vec()]).unwrap();
+        assert_eq!(pixels(&picture.picture, 2, 1..10), [BACKGROUND, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, BACKGROUND]);
+        assert_eq!(pixels(&picture.picture, 5, 1..10), [BACKGROUND, 0x22, 0x11, 0x11, 0x11, 0x11, 0x11, 0x22, BACKGROUND]);
+        assert_eq!(pixels(&picture.picture, 20, 20..31), [BACKGROUND; 11]);
+        assert!(picture.priority.iter().chain(&picture.control).all(|&p| p == 0));
+    }
+
+    #[test]
+    fn it_draws_priority_and_control_separately() {
+        let base = [&[0xf2, 5][..], &BOX, &[0xf2, 7, 0xf8, 0, 5, 5, 0xff]].concat(); // Priority only, filled within the box.
+        let overlay = [0xfb, 3, 0xf6, 0, 0, 10, 0, 9, 10, 0xff]; // Control only.
+        let mut picture = Picture::composite(&[base]).unwrap();
+        picture.draw_over(&overlay).unwrap();
+        assert_eq!(pixels(&picture.priority, 5, 1..10), [0, 5, 7, 7, 7, 7, 7, 5, 0]);
+        assert_eq!(pixels(&picture.priority, 10, 0..10), [0; 10]);
+        assert_eq!(pixels(&picture.control, 10, 0..11), [3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 0]);
+        assert_eq!(pixels(&picture.control, 5, 1..10), [0; 9]);
+        assert!(picture.picture.iter().all(|&p| p == BACKGROUND));
+        assert!(Picture::composite(&[vec![0xf6, 0, 1]]).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+// This is responsible for turning a textual listing of actions back into a picture resource.
+// It accepts the format written by the picture disassembler, so a picture can be dumped, hand-edited and rebuilt.
+// Leading 'xxxx:' byte offsets and anything after a ';' are ignored, so offsets don't need fixing up after edits.
+// Coordinates are always absolute, the relative encodings are calculated here.
+// As well as the explicit encodings, 'Lines' and 'Patterns' pick the smallest encoding that fits.
+// https://sciwiki.sierrahelp.com/index.php/Picture_Resource
+
+use crate::picture_splitter::Action;
+
+pub fn assemble(text: &str) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut is_pattern = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default();
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.first().is_some_and(|t| t.ends_with(':')) {
+            tokens.remove(0); // Byte offset.
+        }
+        if tokens.is_empty() { continue }
+        if let Err(message) = assemble_line(&tokens, &mut is_pattern, &mut out) {
+            panic!("Picture listing line {}: {}", i + 1, message);
+        }
+    }
+    out
+}
+
+fn assemble_line(tokens: &[&str], is_pattern: &mut bool, out: &mut Vec<u8>) -> Result<(), String> {
+    let name = tokens[0];
+    let operands = &tokens[1..];
+    match name {
+        "Lines" => {
+            let points = parse_points(operands)?;
+            let action = smallest_encoding(&points, Action::ShortRelativeLines, Action::MediumRelativeLines, Action::LongLines);
+            return encode_points(action, &points, false, out);
+        }
+        "Patterns" => {
+            let points = parse_points(operands)?;
+            let action = smallest_encoding(&points, Action::ShortRelativePatterns, Action::MediumRelativePatterns, Action::LongPatterns);
+            return encode_points(action, &points, *is_pattern, out);
+        }
+        _ => {}
+    }
+    let action = action_from_name(name).ok_or(format!("Unknown action: {}", name))?;
+    match action {
+        Action::SetVisualColour | Action::SetPriorityColour | Action::SetControlColour => {
+            let [value] = operands else { return Err(format!("{} needs one operand", name)) };
+            out.push(action.to_byte());
+            out.push(parse_byte(value)?);
+        }
+        Action::DisableVisual | Action::DisablePriority | Action::DisableControl | Action::End => {
+            if !operands.is_empty() { return Err(format!("{} has no operands", name)) }
+            out.push(action.to_byte());
+        }
+        Action::SetPattern => {
+            let flags = parse_pattern_flags(operands)?;
+            *is_pattern = flags & 0x20 != 0;
+            out.push(action.to_byte());
+            out.push(flags);
+        }
+        Action::LongLines | Action::MediumRelativeLines | Action::ShortRelativeLines | Action::FloodFill => {
+            let points = parse_points(operands)?;
+            encode_points(action, &points, false, out)?;
+        }
+        Action::LongPatterns | Action::MediumRelativePatterns | Action::ShortRelativePatterns => {
+            let points = parse_points(operands)?;
+            encode_points(action, &points, *is_pattern, out)?;
+        }
+        Action::CommandExtensions => {
+            out.push(action.to_byte());
+            encode_extension(operands, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn action_from_name(name: &str) -> Option<Action> {
+    (0xf0..=0xff).map(Action::from_byte).find(|a| format!("{:?}", a) == name)
+}
+
+// Either a raw byte, or: size rectangle|circle solid|textured.
+fn parse_pattern_flags(operands: &[&str]) -> Result<u8, String> {
+    match operands {
+        [raw] => parse_byte(raw),
+        [size, shape, fill] => {
+            let size = parse_byte(size)?;
+            if size > 7 { return Err(format!("Pattern size must be 0-7: {}", size)) }
+            let shape = match *shape {
+                "circle" => 0,
+                "rectangle" => 0x10,
+                _ => return Err(format!("Pattern shape must be circle or rectangle: {}", shape)),
+            };
+            let fill = match *fill {
+                "solid" => 0,
+                "textured" => 0x20,
+                _ => return Err(format!("Pattern fill must be solid or textured: {}", fill)),
+            };
+            Ok(size | shape | fill)
+        }
+        _ => Err("SetPattern needs a raw byte, or size shape fill".to_string()),
+    }
+}
+
+fn encode_extension(operands: &[&str], out: &mut Vec<u8>) -> Result<(), String> {
+    let Some((&command, rest)) = operands.split_first() else { return Err("CommandExtensions needs a subcommand".to_string()) };
+    match command {
+        "SetPaletteEntries" => {
+            out.push(0);
+            for entry in rest {
+                let (index, colour) = entry.split_once('=').ok_or(format!("Palette entry should be index=colour: {}", entry))?;
+                out.push(parse_byte(index)?);
+                out.push(parse_byte(colour)?);
+            }
+        }
+        "SetPalette" => {
+            let Some((palette_number, colours)) = rest.split_first() else { return Err("SetPalette needs a palette number".to_string()) };
+            if colours.len() != 40 { return Err(format!("SetPalette needs 40 colours, got {}", colours.len())) }
+            out.push(1);
+            out.push(parse_byte(palette_number)?);
+            for colour in colours {
+                out.push(parse_hex_byte(colour)?);
+            }
+        }
+        "Extension" => {
+            let Some((number, bytes)) = rest.split_first() else { return Err("Extension needs a subcommand number".to_string()) };
+            out.push(parse_byte(number)?);
+            for b in bytes {
+                out.push(parse_hex_byte(b)?);
+            }
+        }
+        _ => return Err(format!("Unknown extension: {}", command)),
+    }
+    Ok(())
+}
+
+// An absolute coordinate, with the texture number if the pattern is textured.
+struct Point {
+    x: isize,
+    y: isize,
+    texture: Option<u8>,
+}
+
+// Parses '(x,y)' or '(x,y)#texture' points, whitespace is allowed anywhere.
+fn parse_points(operands: &[&str]) -> Result<Vec<Point>, String> {
+    let joined: String = operands.concat();
+    let mut points: Vec<Point> = Vec::new();
+    let mut remaining = joined.as_str();
+    while !remaining.is_empty() {
+        let Some(after_open) = remaining.strip_prefix('(') else { return Err(format!("Expected '(' at: {}", remaining)) };
+        let (xy, after_close) = after_open.split_once(')').ok_or(format!("Expected ')' at: {}", remaining))?;
+        let (x, y) = xy.split_once(',').ok_or(format!("Expected x,y: {}", xy))?;
+        let x: isize = x.parse().map_err(|_| format!("Bad x: {}", x))?;
+        let y: isize = y.parse().map_err(|_| format!("Bad y: {}", y))?;
+        remaining = after_close;
+        let mut texture: Option<u8> = None;
+        if let Some(after_hash) = remaining.strip_prefix('#') {
+            let end = after_hash.find('(').unwrap_or(after_hash.len());
+            texture = Some(parse_byte(&after_hash[..end])?);
+            remaining = &after_hash[end..];
+        }
+        points.push(Point { x, y, texture });
+    }
+    Ok(points)
+}
+
+fn smallest_encoding(points: &[Point], short: Action, medium: Action, long: Action) -> Action {
+    let deltas: Vec<(isize, isize)> = points.windows(2).map(|w| (w[1].x - w[0].x, w[1].y - w[0].y)).collect();
+    if deltas.iter().all(|&(dx, dy)| short_delta(dx, dy).is_some()) {
+        short
+    } else if deltas.iter().all(|&(dx, dy)| medium_delta(dx, dy).is_some()) {
+        medium
+    } else {
+        long
+    }
+}
+
+fn encode_points(action: Action, points: &[Point], is_pattern: bool, out: &mut Vec<u8>) -> Result<(), String> {
+    let is_fill = action == Action::FloodFill;
+    if points.is_empty() && !is_fill { return Err(format!("{:?} needs at least one point", action)) }
+    let is_long = matches!(action, Action::LongLines | Action::LongPatterns | Action::FloodFill);
+    out.push(action.to_byte());
+    for (i, p) in points.iter().enumerate() {
+        // Texture byte comes first, but only for textured patterns:
+        match (is_pattern, p.texture) {
+            (true, Some(t)) if t >= 0xf0 => return Err(format!("Texture must be below 0xf0: {}", t)),
+            (true, Some(t)) => out.push(t),
+            (true, None) => return Err("Textured patterns need a texture for each point, eg (x,y)#n".to_string()),
+            (false, Some(_)) => return Err("Texture given, but the pattern is solid".to_string()),
+            (false, None) => {}
+        }
+        if i == 0 || is_long {
+            out.extend_from_slice(&triple_from_xy(p.x, p.y)?);
+            continue
+        }
+        let dx = p.x - points[i - 1].x;
+        let dy = p.y - points[i - 1].y;
+        match action {
+            Action::ShortRelativeLines | Action::ShortRelativePatterns => {
+                let delta = short_delta(dx, dy).ok_or(format!("Delta {},{} is too big for {:?}", dx, dy, action))?;
+                out.push(delta);
+            }
+            _ => {
+                let delta = medium_delta(dx, dy).ok_or(format!("Delta {},{} is too big for {:?}", dx, dy, action))?;
+                out.extend_from_slice(&delta);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Converts x,y to 3 bytes XY,XX,YY.
+fn triple_from_xy(x: isize, y: isize) -> Result<[u8; 3], String> {
+    if !(0..0xf00).contains(&x) || !(0..0x1000).contains(&y) {
+        return Err(format!("Coordinate out of range: {},{}", x, y));
+    }
+    Ok([(((x >> 8) << 4) | (y >> 8)) as u8, x as u8, y as u8])
+}
+
+// Short deltas are sign-magnitude nibbles, X high, Y low.
+// Bytes >= 0xf0 would be mistaken for an action, so they don't fit.
+fn short_delta(dx: isize, dy: isize) -> Option<u8> {
+    if dx.abs() > 7 || dy.abs() > 7 { return None }
+    let x_nibble = if dx < 0 { 8 | (-dx) } else { dx } as u8;
+    let y_nibble = if dy < 0 { 8 | (-dy) } else { dy } as u8;
+    let byte = (x_nibble << 4) | y_nibble;
+    if byte >= 0xf0 { None } else { Some(byte) }
+}
+
+// Medium deltas are a sign-magnitude Y byte, then a 2s complement X byte.
+fn medium_delta(dx: isize, dy: isize) -> Option<[u8; 2]> {
+    if dy.abs() > 0x7f || !(-128..=127).contains(&dx) { return None }
+    let y_byte = if dy < 0 { 0x80 | (-dy) } else { dy } as u8;
+    if y_byte >= 0xf0 { return None }
+    Some([y_byte, dx as i8 as u8])
+}
+
+// Accepts decimal or 0x-prefixed hex.
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("Bad byte: {}", s))
+}
+
+fn parse_hex_byte(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| format!("Bad hex byte: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::picture_disassembler;
+    use crate::resource_reader::tests::sample_resources;
+
+    #[test]
+    fn it_assembles() {
+        let listing = "SetVisualColour 1\n\
+            Lines (10,20) (11,22) (10,20) ; Fits in short.\n\
+            Lines (10,20) (100,20)\n\
+            End\n";
+        let expected: Vec<u8> = vec![
+            0xf0, 1,
+            0xf7, 0x00, 10, 20, 0x12, 0x9a,
+            0xf5, 0x00, 10, 20, 0, 90,
+            0xff,
+        ];
+        assert_eq!(assemble(listing), expected);
+    }
+
+    #[test]
+    fn it_round_trips_sample_pictures() {
+        for (entry, resource) in sample_resources() {
+            if entry.resource_type != map::ResourceType::Picture { continue }
+            let listing = picture_disassembler::disassemble(&resource).unwrap();
+            assert_eq!(assemble(&listing), resource, "Picture {} did not round trip", entry.resource_number);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// This is responsible for turning a picture resource into a textual listing, one line per action.
+// Each line starts with the byte offset of the action within the resource, then the action name
+// and its operands with all the coordinate encodings decoded into absolute x,y positions.
+// Everything after a ';' is a comment describing the decoded state (palette, pattern flags, raw bytes).
+// The listing is designed to be fed back into the picture assembler.
+// https://sciwiki.sierrahelp.com/index.php/Picture_Resource
+
+use crate::picture::{self, PALETTE_COUNT, PALETTE_SIZE};
+use crate::picture_splitter::{self, Action, ActionArguments};
+
+pub fn disassemble(data: &[u8]) -> Result<String, String> {
+    let actions = picture_splitter::split(data)?;
+    Ok(listing(&actions))
+}
+
+pub fn listing(actions: &[ActionArguments]) -> String {
+    let mut out = String::new();
+    let mut offset: usize = 0;
+    let mut is_pattern = false;
+    let mut palettes = [picture::DEFAULT_PALETTE; PALETTE_COUNT];
+    for a in actions {
+        let (operands, comment) = describe(a, &mut is_pattern, &mut palettes);
+        let mut line = format!("{:04x}: {:?}", offset, a.action);
+        if !operands.is_empty() {
+            line.push(' ');
+            line.push_str(&operands);
+        }
+        if !comment.is_empty() {
+            line.push_str(" ; ");
+            line.push_str(&comment);
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+        offset += 1 + a.arguments.len();
+    }
+    out
+}
+
+// Returns the operands and the comment for an action.
+// The pattern flag and palettes are tracked as they affect how later actions are interpreted.
+fn describe(a: &ActionArguments, is_pattern: &mut bool, palettes: &mut [[u8; PALETTE_SIZE]; PALETTE_COUNT]) -> (String, String) {
+    let args = &a.arguments;
+    match a.action {
+        Action::SetVisualColour => {
+            let Some(&value) = args.first() else { return (String::new(), String::new()) };
+            let index = (value as usize) % PALETTE_SIZE;
+            let palette_number = (value as usize) / PALETTE_SIZE;
+            let comment = match palettes.get(palette_number) {
+                Some(palette) => format!("palette {} entry {} = {}", palette_number, index, describe_dither(palette[index])),
+                None => format!("palette {} doesn't exist, so the colour is unchanged", palette_number),
+            };
+            (format!("{}", value), comment)
+        }
+        Action::SetPriorityColour | Action::SetControlColour => {
+            let Some(&value) = args.first() else { return (String::new(), String::new()) };
+            (format!("{}", value), String::new())
+        }
+        Action::DisableVisual | Action::DisablePriority | Action::DisableControl | Action::End => {
+            (String::new(), String::new())
+        }
+        Action::SetPattern => {
+            let Some(&flags) = args.first() else { return (String::new(), String::new()) };
+            *is_pattern = flags & 0x20 != 0;
+            let operands = if flags & !0x37 != 0 {
+                format!("0x{:02x}", flags) // Unknown bits are set, so keep the raw byte.
+            } else {
+                format!("{} {} {}",
+                    flags & 7,
+                    if flags & 0x10 != 0 { "rectangle" } else { "circle" },
+                    if flags & 0x20 != 0 { "textured" } else { "solid" })
+            };
+            (operands, format!("0x{:02x}", flags))
+        }
+        Action::LongLines => (describe_points(&decode_long(args, 0)), String::new()),
+        Action::MediumRelativeLines => (describe_points(&decode_medium(args, 0)), String::new()),
+        Action::ShortRelativeLines => (describe_points(&decode_short(args, 0)), String::new()),
+        Action::FloodFill => (describe_points(&decode_long(args, 0)), String::new()),
+        Action::LongPatterns => {
+            (describe_points(&decode_long(args, if *is_pattern { 1 } else { 0 })), String::new())
+        }
+        Action::MediumRelativePatterns => {
+            (describe_points(&decode_medium(args, if *is_pattern { 1 } else { 0 })), String::new())
+        }
+        Action::ShortRelativePatterns => {
+            (describe_points(&decode_short(args, if *is_pattern { 1 } else { 0 })), String::new())
+        }
+        Action::CommandExtensions => describe_extension(args, palettes),
+    }
+}
+
+fn describe_extension(args: &[u8], palettes: &mut [[u8; PALETTE_SIZE]; PALETTE_COUNT]) -> (String, String) {
+    let Some(&command) = args.first() else { return (String::new(), String::new()) };
+    let ext_args = &args[1..];
+    match command {
+        0 => { // Set palette entries.
+            let mut entries: Vec<String> = Vec::new();
+            let mut changes: Vec<String> = Vec::new();
+            for chunk in ext_args.chunks_exact(2) {
+                let index = (chunk[0] as usize) % PALETTE_SIZE;
+                let palette_number = (chunk[0] as usize) / PALETTE_SIZE;
+                entries.push(format!("{}=0x{:02x}", chunk[0], chunk[1]));
+                if let Some(palette) = palettes.get_mut(palette_number) {
+                    palette[index] = chunk[1];
+                    changes.push(format!("{}.{} = {}", palette_number, index, describe_dither(chunk[1])));
+                } else {
+                    changes.push(format!("{}.{} ignored", palette_number, index));
+                }
+            }
+            (format!("SetPaletteEntries {}", entries.join(" ")), changes.join(", "))
+        }
+        1 => { // Set entire palette.
+            let Some(&palette_number) = ext_args.first() else { return ("SetPalette".to_string(), String::new()) };
+            let colours = &ext_args[1..];
+            if let Some(palette) = palettes.get_mut(palette_number as usize) {
+                for (i, colour) in colours.iter().enumerate().take(PALETTE_SIZE) {
+                    palette[i] = *colour;
+                }
+            }
+            let hex: Vec<String> = colours.iter().map(|c| format!("{:02x}", c)).collect();
+            (format!("SetPalette {} {}", palette_number, hex.join(" ")), String::new())
+        }
+        _ => {
+            let hex: Vec<String> = ext_args.iter().map(|c| format!("{:02x}", c)).collect();
+            let name = match command {
+                2 => "monochrome set palette",
+                3 => "monochrome set visual",
+                4 => "monochrome disable visual",
+                5 => "monochrome set direct visual",
+                6 => "monochrome disable direct visual",
+                7 => "embed cel",
+                8 => "set priority bands",
+                _ => "unknown",
+            };
+            (format!("Extension {} {}", command, hex.join(" ")), format!("{}: {}", name, picture::describe_buf(ext_args)))
+        }
+    }
+}
+
+// A decoded coordinate, with the texture number if the pattern is textured.
+pub struct Point {
+    pub x: isize,
+    pub y: isize,
+    pub texture: Option<u8>,
+}
+
+fn describe_points(points: &[Point]) -> String {
+    let strings: Vec<String> = points.iter().map(|p| {
+        match p.texture {
+            Some(t) => format!("({},{})#{}", p.x, p.y, t),
+            None => format!("({},{})", p.x, p.y),
+        }
+    }).collect();
+    strings.join(" ")
+}
+
+// Reads the optional texture byte that precedes each coordinate of a textured pattern.
+fn take_texture(data: &[u8], texture_bytes: usize) -> (Option<u8>, &[u8]) {
+    if texture_bytes == 0 { (None, data) } else { (Some(data[0]), &data[1..]) }
+}
+
+// Long coordinates are all absolute 3-byte triples.
+pub fn decode_long(args: &[u8], texture_bytes: usize) -> Vec<Point> {
+    args.chunks_exact(3 + texture_bytes).map(|chunk| {
+        let (texture, xy) = take_texture(chunk, texture_bytes);
+        let (x, y) = picture::xy_from_triple(xy);
+        Point { x: x as isize, y: y as isize, texture }
+    }).collect()
+}
+
+// Medium coordinates are an absolute triple, followed by 2-byte deltas.
+// Y uses sign-magnitude, X uses 2s complement.
+pub fn decode_medium(args: &[u8], texture_bytes: usize) -> Vec<Point> {
+    if args.len() < 3 + texture_bytes { return Vec::new() }
+    let (texture, after_texture) = take_texture(args, texture_bytes);
+    let (x, y) = picture::xy_from_triple(after_texture);
+    let (mut x, mut y) = (x as isize, y as isize);
+    let mut points = vec![Point { x, y, texture }];
+    for chunk in after_texture[3..].chunks_exact(2 + texture_bytes) {
+        let (texture, delta) = take_texture(chunk, texture_bytes);
+        let y_raw = (delta[0] & 0x7f) as isize;
+        y = if delta[0] & 0x80 != 0 { y - y_raw } else { y + y_raw };
+        x += (delta[1] as i8) as isize;
+        points.push(Point { x, y, texture });
+    }
+    points
+}
+
+// Short coordinates are an absolute triple, followed by 1-byte deltas.
+// X is the high nibble, Y the low nibble, both sign-magnitude.
+pub fn decode_short(args: &[u8], texture_bytes: usize) -> Vec<Point> {
+    if args.len() < 3 + texture_bytes { return Vec::new() }
+    let (texture, after_texture) = take_texture(args, texture_bytes);
+    let (x, y) = picture::xy_from_triple(after_texture);
+    let (mut x, mut y) = (x as isize, y as isize);
+    let mut points = vec![Point { x, y, texture }];
+    for chunk in after_texture[3..].chunks_exact(1 + texture_bytes) {
+        let (texture, delta) = take_texture(chunk, texture_bytes);
+        let xy = delta[0];
+        let x_raw = ((xy >> 4) & 7) as isize;
+        x = if xy & 0x80 != 0 { x - x_raw } else { x + x_raw };
+        let y_raw = (xy & 7) as isize;
+        y = if xy & 8 != 0 { y - y_raw } else { y + y_raw };
+        points.push(Point { x, y, texture });
+    }
+    points
+}
+
+// Describes a palette entry, which is two EGA colours that get dithered together.
+fn describe_dither(value: u8) -> String {
+    const NAMES: [&str; 16] = [
+        "black", "blue", "green", "cyan", "red", "magenta", "brown", "light gray",
+        "dark gray", "bright blue", "bright green", "bright cyan", "bright red", "bright magenta", "yellow", "white",
+    ];
+    let a = value >> 4;
+    let b = value & 0xf;
+    if a == b {
+        format!("0x{:02x} {}", value, NAMES[a as usize])
+    } else {
+        format!("0x{:02x} {}/{}", value, NAMES[a as usize], NAMES[b as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists() {
+        let resource: Vec<u8> = vec![
+            0xf0, 1,
+            0xf7, 0x00, 10, 20, 0x12, 0x9a,
+            0xff,
+        ];
+        let expected = "0000: SetVisualColour 1 ; palette 0 entry 1 = 0x11 blue\n\
+            0002: ShortRelativeLines (10,20) (11,22) (10,20)\n\
+            0008: End\n";
+        assert_eq!(disassemble(&resource).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_tracks_each_palette() {
+        let resource: Vec<u8> = vec![
+            0xfe, 0, 41, 0x44,
+            0xf0, 41,
+            0xf0, 1,
+            0xf0, 200,
+            0xff,
+        ];
+        let expected = "0000: CommandExtensions SetPaletteEntries 41=0x44 ; 1.1 = 0x44 red\n\
+            0004: SetVisualColour 41 ; palette 1 entry 1 = 0x44 red\n\
+            0006: SetVisualColour 1 ; palette 0 entry 1 = 0x11 blue\n\
+            0008: SetVisualColour 200 ; palette 5 doesn't exist, so the colour is unchanged\n\
+            000a: End\n";
+        assert_eq!(disassemble(&resource).unwrap(), expected);
+    }
+}
@@ -31,15 +31,21 @@ pub struct ActionArguments {
     pub arguments: Vec<u8>,
 }
 
-pub fn split(data: &[u8]) -> Vec<ActionArguments> {
+// Corrupt data gives an error, eg an argument that isn't an action, or arguments that run past the end.
+pub fn split(data: &[u8]) -> Result<Vec<ActionArguments>, String> {
     let mut remaining = data;
     let mut actions: Vec<ActionArguments> = Vec::new();
     let mut is_pattern = false;
     loop {
         if remaining.is_empty() { break }
+        let offset = data.len() - remaining.len();
+        if remaining[0] < 0xf0 {
+            return Err(format!("Expected an action at {}, found {:02x}", offset, remaining[0]))
+        }
         let action = Action::from_byte(remaining[0]);
         let args_onwards = &remaining[1..];
-        let args_len = desired_arguments_length(action, args_onwards, is_pattern);
+        let args_len = desired_arguments_length(action, args_onwards, is_pattern)
+            .map_err(|e| format!("{:?} at {} {}", action, offset, e))?;
         let args = &args_onwards[..args_len];
         if action == Action::SetPattern {
             is_pattern = args[0] & 0x20 != 0;
@@ -50,37 +56,45 @@ pub fn split(data: &[u8]) -> Vec<ActionArguments> {
         });
         remaining = &remaining[(1 + args_len)..];
     }
-    actions
+    Ok(actions)
+}
+
+// How many argument bytes are desired for this code, checking they're all there.
+fn desired_arguments_length(action: Action, args: &[u8], is_pattern: bool) -> Result<usize, String> {
+    let bytes = arguments_length(action, args, is_pattern)?;
+    if bytes > args.len() {
+        return Err(format!("wants {} argument bytes, but only {} are left", bytes, args.len()))
+    }
+    Ok(bytes)
 }
 
-// How many argument bytes are desired for this code.
-fn desired_arguments_length(action: Action, args: &[u8], is_pattern: bool) -> usize {
-    match action {
+fn arguments_length(action: Action, args: &[u8], is_pattern: bool) -> Result<usize, String> {
+    Ok(match action {
         Action::SetVisualColour => { 1 }
         Action::DisableVisual => { 0 }
         Action::SetPriorityColour => { 1 }
         Action::DisablePriority => { 0 }
         Action::SetPattern => { 1 }
-        Action::ShortRelativePatterns => { desired_arguments_length_short_patterns(args, is_pattern) }
-        Action::MediumRelativePatterns => { desired_arguments_length_medium_patterns(args, is_pattern) }
-        Action::LongPatterns => { desired_arguments_length_long_patterns(args, is_pattern) }
-        Action::ShortRelativeLines => { desired_arguments_length_short_lines(args) }
-        Action::MediumRelativeLines => { desired_arguments_length_medium_lines(args) }
-        Action::LongLines => { desired_arguments_length_long_lines(args) }
-        Action::FloodFill => { desired_arguments_length_fills(args) }
+        Action::ShortRelativePatterns => { desired_arguments_length_short_patterns(args, is_pattern)? }
+        Action::MediumRelativePatterns => { desired_arguments_length_medium_patterns(args, is_pattern)? }
+        Action::LongPatterns => { desired_arguments_length_long_patterns(args, is_pattern)? }
+        Action::ShortRelativeLines => { desired_arguments_length_short_lines(args)? }
+        Action::MediumRelativeLines => { desired_arguments_length_medium_lines(args)? }
+        Action::LongLines => { desired_arguments_length_long_lines(args)? }
+        Action::FloodFill => { desired_arguments_length_fills(args)? }
         Action::SetControlColour => { 1 }
         Action::DisableControl => { 0 }
-        Action::CommandExtensions => { desired_arguments_length_extensions(args) }
+        Action::CommandExtensions => { desired_arguments_length_extensions(args)? }
         Action::End => { 0 }
-    }
+    })
 }
 
-fn desired_arguments_length_extensions(args: &[u8]) -> usize {
-    let command = args[0];
-    match command {
+fn desired_arguments_length_extensions(args: &[u8]) -> Result<usize, String> {
+    let command = byte(args, 0)?;
+    Ok(match command {
         0 => { // Set palette entries.
             let mut bytes = 1;
-            while args[bytes] < 0xf0 {
+            while byte(args, bytes)? < 0xf0 {
                 bytes += 2;
             }
             bytes
@@ -94,84 +108,89 @@ fn desired_arguments_length_extensions(args: &[u8]) -> usize {
         5 => { 2 }, // Monochrome 3: set direct visual.
         6 => { 1 }, // Monochrome 4: disable direct visual.
         7 => { // Embed cel (SCI01).
-            let size = (args[4] as usize) + ((args[5] as usize) << 8);
+            let size = (byte(args, 4)? as usize) + ((byte(args, 5)? as usize) << 8);
             6 + size // Command (1) + XY (3) + cel size (2) + cel (n).
         },
         8 => { // Set priority bands (SCI01).
             15 // Command (1) + Priority table (14).
         },
         _ => {
-            panic!("Unrecognised extended operation! Command: {}", command);
+            return Err(format!("has an unrecognised extended operation: {}", command))
         },
-    }
+    })
 }
 
-fn desired_arguments_length_short_lines(args: &[u8]) -> usize {
+// The argument at the index, or an error if the data ends first.
+fn byte(args: &[u8], index: usize) -> Result<u8, String> {
+    args.get(index).copied().ok_or(format!("runs past the end after {} argument bytes", args.len()))
+}
+
+fn desired_arguments_length_short_lines(args: &[u8]) -> Result<usize, String> {
     let mut bytes = 3;
-    while args[bytes] < 0xf0 {
+    while byte(args, bytes)? < 0xf0 {
         bytes += 1;
     }
-    return bytes;
+    Ok(bytes)
 }
 
-fn desired_arguments_length_medium_lines(args: &[u8]) -> usize {
+fn desired_arguments_length_medium_lines(args: &[u8]) -> Result<usize, String> {
     let mut bytes = 3;
-    while args[bytes] < 0xf0 {
+    while byte(args, bytes)? < 0xf0 {
         bytes += 2;
     }
-    return bytes;
+    Ok(bytes)
 }
 
 // Long lines are at least 1 multiple of 3.
-fn desired_arguments_length_long_lines(args: &[u8]) -> usize {
+fn desired_arguments_length_long_lines(args: &[u8]) -> Result<usize, String> {
     let mut bytes = 3;
     loop {
-        if args[bytes] >= 0xf0 { break }
+        if byte(args, bytes)? >= 0xf0 { break }
         bytes += 3;
     }
-    return bytes;
+    Ok(bytes)
 }
 
 // Fills are 0 or more multiples of 3.
-fn desired_arguments_length_fills(args: &[u8]) -> usize {
+fn desired_arguments_length_fills(args: &[u8]) -> Result<usize, String> {
     let mut bytes = 0;
     loop {
-        if args[bytes] >= 0xf0 { break }
+        if byte(args, bytes)? >= 0xf0 { break }
         bytes += 3;
     }
-    return bytes;
+    Ok(bytes)
 }
 
 // Long patterns are chunks of lengths + a pattern byte if pattern mode.
-fn desired_arguments_length_long_patterns(args: &[u8], is_pattern: bool) -> usize {
+fn desired_arguments_length_long_patterns(args: &[u8], is_pattern: bool) -> Result<usize, String> {
     let mut bytes = 0;
     let chunk_size = if is_pattern { 4 } else { 3 };
-    while args[bytes] < 0xf0 {
+    while byte(args, bytes)? < 0xf0 {
         bytes += chunk_size;
     }
-    return bytes;
+    Ok(bytes)
 }
 
-fn desired_arguments_length_medium_patterns(args: &[u8], is_pattern: bool) -> usize {
+fn desired_arguments_length_medium_patterns(args: &[u8], is_pattern: bool) -> Result<usize, String> {
     let mut bytes = if is_pattern { 4 } else { 3 };
     let chunk_size = if is_pattern { 3 } else { 2 };
-    while args[bytes] < 0xf0 {
+    while byte(args, bytes)? < 0xf0 {
         bytes += chunk_size;
     }
-    return bytes;
+    Ok(bytes)
 }
 
-fn desired_arguments_length_short_patterns(args: &[u8], is_pattern: bool) -> usize {
+fn desired_arguments_length_short_patterns(args: &[u8], is_pattern: bool) -> Result<usize, String> {
     let mut bytes = if is_pattern { 4 } else { 3 };
     let chunk_size = if is_pattern { 2 } else { 1 };
-    while args[bytes] < 0xf0 {
+    while byte(args, bytes)? < 0xf0 {
         bytes += chunk_size;
     }
-    return bytes;
+    Ok(bytes)
 }
 
 impl Action {
-    fn from_byte(b: u8) -> Self {
+    pub fn from_byte(b: u8) -> Self {
         match b {
             0xF0 => Self::SetVisualColour, 
             0xF1 => Self::DisableVisual, 
@@ -192,6 +211,27 @@ impl Action {
             _ => panic!("Unrecognised action type!"),
         }
     }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::SetVisualColour => 0xF0,
+            Self::DisableVisual => 0xF1,
+            Self::SetPriorityColour => 0xF2,
+            Self::DisablePriority => 0xF3,
+            Self::ShortRelativePatterns => 0xF4,
+            Self::MediumRelativeLines => 0xF5,
+            Self::LongLines => 0xF6,
+            Self::ShortRelativeLines => 0xF7,
+            Self::FloodFill => 0xF8,
+            Self::SetPattern => 0xF9,
+            Self::LongPatterns => 0xFA,
+            Self::SetControlColour => 0xFB,
+            Self::DisableControl => 0xFC,
+            Self::MediumRelativePatterns => 0xFD,
+            Self::CommandExtensions => 0xFE,
+            Self::End => 0xFF,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -205,7 +245,7 @@ mod tests {
             0xf1,
             0xff,
         ];
-        let result = super::split(&resource);
+        let result = super::split(&resource).unwrap();
         let expected: Vec<super::ActionArguments> = vec![
             ActionArguments{
                 action: Action::SetVisualColour,
@@ -1,44 +1,398 @@
 // This file contains enough code to write a very naive PNG format without needing
 // a massive tree of dependencies.
 
-// Converts into an RFC1951 'raw deflate stream' format in a simple non-compressing way.
-// An uncompressed deflate stream looks like N blocks, each being:
-// [is_final, 2 bytes length, 2 bytes length 1's complement, data].
-// Motivation: Avoid dependencies, simple implementation, meet requirements of PNG storage.
+use crate::bitstream_lsb::BitWriterLSB;
+use crate::inflate;
+
+// Converts into an RFC1951 'raw deflate stream'.
+// The input is LZ77 compressed into literals and back-references, which are split into blocks.
+// Each block is written whichever way is smallest: stored, fixed Huffman codes, or dynamic Huffman codes.
+// Motivation: Avoid dependencies, while not needing pngquant/apngasm to get reasonable file sizes.
 // See: https://datatracker.ietf.org/doc/html/rfc1951
 // Test output with: ruby -rzlib -e 'print Zlib::Inflate.new(-15).inflate(STDIN.read)' < foo.deflateStream
 // https://yob.id.au/2020/06/16/zlib-gzip-and-deflate-in-ruby.html
 fn to_deflate_stream(input: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriterLSB::new();
     if input.is_empty() {
-        return vec![1, 0, 0, 0xff, 0xff]; // 1 block with no content.
+        write_stored_block(&mut writer, &[], true);
+        return writer.finish();
     }
-    let mut output = Vec::<u8>::new();
-    let chunks = input.chunks(0xffff);
+    let tokens = lz77(input);
+    let blocks: Vec<&[Token]> = tokens.chunks(TOKENS_PER_BLOCK).collect();
+    let mut input_offset = 0;
+    for (index, block) in blocks.iter().enumerate() {
+        let is_final = index == blocks.len() - 1;
+        let input_length: usize = block.iter().map(|t| t.input_length()).sum();
+        let raw = &input[input_offset..(input_offset + input_length)];
+        input_offset += input_length;
+        write_block(&mut writer, block, raw, is_final);
+    }
+    writer.finish()
+}
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 64; // How many earlier positions to check for a match, trading speed for size.
+const NICE_MATCH: usize = 128; // Stop looking once a match is this long.
+const TOKENS_PER_BLOCK: usize = 16384;
+
+const END_OF_BLOCK: usize = 256;
+const LENGTH_BASE: [usize; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [usize; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [usize; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [usize; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Either a literal byte (length 0), or a back-reference of length 3-258 to distance 1-32768.
+#[derive(Clone, Copy)]
+struct Token {
+    length: u16,
+    value: u16, // The literal byte, or the distance.
+}
+
+impl Token {
+    fn literal(byte: u8) -> Token {
+        Token { length: 0, value: byte as u16 }
+    }
+
+    fn input_length(&self) -> usize {
+        if self.length == 0 { 1 } else { self.length as usize }
+    }
+}
+
+// Finds repeated strings using hash chains of earlier positions that start with the same 3 bytes.
+// Uses 'lazy' matching, where a match is skipped if the next position has a longer one.
+fn lz77(input: &[u8]) -> Vec<Token> {
+    let mut chains = HashChains {
+        head: vec![usize::MAX; 1 << HASH_BITS],
+        previous: vec![usize::MAX; WINDOW_SIZE],
+    };
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let (length, distance) = chains.longest_match(input, i);
+        chains.insert(input, i);
+        if length < MIN_MATCH || (length < NICE_MATCH && chains.longest_match(input, i + 1).0 > length) {
+            tokens.push(Token::literal(input[i]));
+            i += 1;
+            continue
+        }
+        tokens.push(Token { length: length as u16, value: distance as u16 });
+        for j in (i + 1)..(i + length) {
+            chains.insert(input, j);
+        }
+        i += length;
+    }
+    tokens
+}
+
+struct HashChains {
+    head: Vec<usize>, // Most recent position for each hash.
+    previous: Vec<usize>, // Earlier position with the same hash, indexed by position modulo the window.
+}
+
+impl HashChains {
+    fn hash(input: &[u8], i: usize) -> usize {
+        (((input[i] as usize) << 10) ^ ((input[i + 1] as usize) << 5) ^ (input[i + 2] as usize)) & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, input: &[u8], i: usize) {
+        if i + MIN_MATCH > input.len() { return }
+        let h = HashChains::hash(input, i);
+        self.previous[i % WINDOW_SIZE] = self.head[h];
+        self.head[h] = i;
+    }
+
+    // Returns the length and distance of the longest earlier match, or 0 length if none.
+    fn longest_match(&self, input: &[u8], i: usize) -> (usize, usize) {
+        if i + MIN_MATCH > input.len() { return (0, 0) }
+        let max_length = MAX_MATCH.min(input.len() - i);
+        let mut best = (0, 0);
+        let mut candidate = self.head[HashChains::hash(input, i)];
+        let mut chain = 0;
+        while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let length = input[candidate..].iter().zip(&input[i..(i + max_length)]).take_while(|(a, b)| a == b).count();
+            if length > best.0 {
+                best = (length, i - candidate);
+                if length >= NICE_MATCH { break }
+            }
+            let next = self.previous[candidate % WINDOW_SIZE];
+            if next == usize::MAX || next >= candidate { break } // Overwritten by a newer position, so the rest is out of the window.
+            candidate = next;
+            chain += 1;
+        }
+        best
+    }
+}
+
+// Returns the code and extra bits value for a length or distance, given the base table.
+fn code_from_base(value: usize, bases: &[usize]) -> (usize, usize) {
+    let code = bases.partition_point(|&b| b <= value) - 1;
+    (code, value - bases[code])
+}
+
+fn write_block(writer: &mut BitWriterLSB, tokens: &[Token], raw: &[u8], is_final: bool) {
+    // Count symbol frequencies.
+    let mut literal_frequencies: Vec<usize> = vec![0; 286];
+    let mut distance_frequencies: Vec<usize> = vec![0; 30];
+    for t in tokens {
+        if t.length == 0 {
+            literal_frequencies[t.value as usize] += 1;
+        } else {
+            literal_frequencies[257 + code_from_base(t.length as usize, &LENGTH_BASE).0] += 1;
+            distance_frequencies[code_from_base(t.value as usize, &DISTANCE_BASE).0] += 1;
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] = 1;
+
+    // Work out the size of each option.
+    let fixed_literal_lengths = fixed_literal_lengths();
+    let fixed_distance_lengths = vec![5; 30];
+    let dynamic_literal_lengths = huffman_lengths(&literal_frequencies, 15);
+    let mut dynamic_distance_lengths = huffman_lengths(&distance_frequencies, 15);
+    if dynamic_distance_lengths.iter().all(|&l| l == 0) {
+        dynamic_distance_lengths[0] = 1; // At least one distance code is needed, even if unused.
+    }
+    let header = dynamic_header(&dynamic_literal_lengths, &dynamic_distance_lengths);
+    let fixed_bits = 3 + data_bits(&literal_frequencies, &distance_frequencies, &fixed_literal_lengths, &fixed_distance_lengths);
+    let dynamic_bits = 3 + header.bits + data_bits(&literal_frequencies, &distance_frequencies, &dynamic_literal_lengths, &dynamic_distance_lengths);
+    let stored_bits = (raw.len() + 5 * raw.len().div_ceil(0xffff).max(1)) * 8 + 10;
+
+    if stored_bits <= fixed_bits.min(dynamic_bits) {
+        write_stored_block(writer, raw, is_final);
+    } else if fixed_bits <= dynamic_bits {
+        writer.write(is_final as usize, 1);
+        writer.write(1, 2); // Fixed Huffman.
+        write_tokens(writer, tokens, &fixed_literal_lengths, &fixed_distance_lengths);
+    } else {
+        writer.write(is_final as usize, 1);
+        writer.write(2, 2); // Dynamic Huffman.
+        header.write(writer);
+        write_tokens(writer, tokens, &dynamic_literal_lengths, &dynamic_distance_lengths);
+    }
+}
+
+// Stored blocks are: [is_final, 2 bytes length, 2 bytes length 1's complement, data], on a byte boundary.
+fn write_stored_block(writer: &mut BitWriterLSB, raw: &[u8], is_final: bool) {
+    let chunks: Vec<&[u8]> = if raw.is_empty() { vec![raw] } else { raw.chunks(0xffff).collect() };
     let final_index = chunks.len() - 1;
-    for (index, chunk) in chunks.enumerate() {
-        let is_final = index == final_index;
-        output.push(if is_final { 1 } else { 0 });
+    for (index, chunk) in chunks.iter().enumerate() {
+        writer.write((is_final && index == final_index) as usize, 1);
+        writer.write(0, 2); // Stored.
+        writer.align();
         let len = chunk.len();
-        let len_lsb = (len & 0xff) as u8;
-        let len_msb = (len >> 8) as u8;
-        output.push(len_lsb); // Max len.
-        output.push(len_msb);
-        output.push(!len_lsb); // 1's complement of len.
-        output.push(!len_msb);
-        output.extend_from_slice(chunk);
+        writer.extend_from_slice(&[len as u8, (len >> 8) as u8, !(len as u8), !((len >> 8) as u8)]);
+        writer.extend_from_slice(chunk);
     }
-    output
 }
 
-// Converts to an uncompressed RFC1950 zlib stream.
+fn fixed_literal_lengths() -> Vec<u8> {
+    let mut lengths = vec![8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+fn data_bits(literal_frequencies: &[usize], distance_frequencies: &[usize], literal_lengths: &[u8], distance_lengths: &[u8]) -> usize {
+    let mut bits = 0;
+    for (symbol, frequency) in literal_frequencies.iter().enumerate() {
+        bits += frequency * (literal_lengths[symbol] as usize);
+        if symbol > END_OF_BLOCK {
+            bits += frequency * LENGTH_EXTRA_BITS[symbol - 257];
+        }
+    }
+    for (symbol, frequency) in distance_frequencies.iter().enumerate() {
+        bits += frequency * ((distance_lengths[symbol] as usize) + DISTANCE_EXTRA_BITS[symbol]);
+    }
+    bits
+}
+
+fn write_tokens(writer: &mut BitWriterLSB, tokens: &[Token], literal_lengths: &[u8], distance_lengths: &[u8]) {
+    let literal_codes = canonical_codes(literal_lengths);
+    let distance_codes = canonical_codes(distance_lengths);
+    for t in tokens {
+        if t.length == 0 {
+            let symbol = t.value as usize;
+            writer.write(literal_codes[symbol], literal_lengths[symbol] as usize);
+        } else {
+            let (code, extra) = code_from_base(t.length as usize, &LENGTH_BASE);
+            writer.write(literal_codes[257 + code], literal_lengths[257 + code] as usize);
+            writer.write(extra, LENGTH_EXTRA_BITS[code]);
+            let (code, extra) = code_from_base(t.value as usize, &DISTANCE_BASE);
+            writer.write(distance_codes[code], distance_lengths[code] as usize);
+            writer.write(extra, DISTANCE_EXTRA_BITS[code]);
+        }
+    }
+    writer.write(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK] as usize);
+}
+
+// The dynamic block header: the code lengths, run-length encoded, then Huffman coded themselves.
+struct DynamicHeader {
+    literal_count: usize,
+    distance_count: usize,
+    code_length_count: usize,
+    code_length_lengths: Vec<u8>,
+    runs: Vec<(usize, usize)>, // Code length symbol 0-18, and its extra bits value.
+    bits: usize,
+}
+
+fn dynamic_header(literal_lengths: &[u8], distance_lengths: &[u8]) -> DynamicHeader {
+    let literal_count = 257.max(literal_lengths.iter().rposition(|&l| l != 0).map_or(0, |p| p + 1));
+    let distance_count = 1.max(distance_lengths.iter().rposition(|&l| l != 0).map_or(0, |p| p + 1));
+    let mut all_lengths: Vec<u8> = literal_lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+
+    // Run-length encode: 16 repeats the previous length 3-6 times, 17 repeats zero 3-10 times, 18 repeats zero 11-138 times.
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < all_lengths.len() {
+        let length = all_lengths[i];
+        let run = all_lengths[i..].iter().take_while(|&&l| l == length).count();
+        if length == 0 && run >= 11 {
+            let count = run.min(138);
+            runs.push((18, count - 11));
+            i += count;
+        } else if length == 0 && run >= 3 {
+            runs.push((17, run - 3));
+            i += run;
+        } else if length != 0 && run >= 4 {
+            runs.push((length as usize, 0));
+            let count = (run - 1).min(6);
+            runs.push((16, count - 3));
+            i += 1 + count;
+        } else {
+            runs.push((length as usize, 0));
+            i += 1;
+        }
+    }
+
+    let mut code_length_frequencies: Vec<usize> = vec![0; 19];
+    for &(symbol, _) in &runs {
+        code_length_frequencies[symbol] += 1;
+    }
+    let code_length_lengths = huffman_lengths(&code_length_frequencies, 7);
+    let code_length_count = 4.max(CODE_LENGTH_ORDER.iter().rposition(|&s| code_length_lengths[s] != 0).map_or(0, |p| p + 1));
+    let mut bits = 5 + 5 + 4 + code_length_count * 3;
+    for &(symbol, _) in &runs {
+        bits += code_length_lengths[symbol] as usize + code_length_extra_bits(symbol);
+    }
+    DynamicHeader { literal_count, distance_count, code_length_count, code_length_lengths, runs, bits }
+}
+
+fn code_length_extra_bits(symbol: usize) -> usize {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+impl DynamicHeader {
+    fn write(&self, writer: &mut BitWriterLSB) {
+        writer.write(self.literal_count - 257, 5);
+        writer.write(self.distance_count - 1, 5);
+        writer.write(self.code_length_count - 4, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write(self.code_length_lengths[symbol] as usize, 3);
+        }
+        let codes = canonical_codes(&self.code_length_lengths);
+        for &(symbol, extra) in &self.runs {
+            writer.write(codes[symbol], self.code_length_lengths[symbol] as usize);
+            writer.write(extra, code_length_extra_bits(symbol));
+        }
+    }
+}
+
+// Works out Huffman code lengths for the given symbol frequencies, no longer than max_length.
+// If the tree is too deep, the frequencies are flattened and it tries again.
+fn huffman_lengths(frequencies: &[usize], max_length: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let lengths = unlimited_huffman_lengths(&frequencies);
+        if lengths.iter().all(|&l| l <= max_length) {
+            return lengths;
+        }
+        for f in frequencies.iter_mut() {
+            if *f > 0 {
+                *f = (*f >> 1) | 1;
+            }
+        }
+    }
+}
+
+fn unlimited_huffman_lengths(frequencies: &[usize]) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    let mut lengths: Vec<u8> = vec![0; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len()).filter(|&s| frequencies[s] > 0).collect();
+    if used.len() == 1 {
+        lengths[used[0]] = 1; // A single symbol still needs a 1-bit code.
+        return lengths;
+    }
+    // Nodes 0..n are the symbols, then merged nodes are appended with their parent recorded.
+    let mut parents: Vec<usize> = vec![usize::MAX; frequencies.len()];
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = used.iter().map(|&s| Reverse((frequencies[s], s))).collect();
+    while heap.len() > 1 {
+        let Reverse((frequency_a, a)) = heap.pop().unwrap();
+        let Reverse((frequency_b, b)) = heap.pop().unwrap();
+        let merged = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = merged;
+        parents[b] = merged;
+        heap.push(Reverse((frequency_a + frequency_b, merged)));
+    }
+    for &s in &used {
+        let mut depth = 0;
+        let mut node = s;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[s] = depth;
+    }
+    lengths
+}
+
+// Assigns canonical codes from code lengths, as described in RFC1951 3.2.2.
+// The codes are returned bit-reversed, as Huffman codes are packed starting from their most significant bit.
+fn canonical_codes(lengths: &[u8]) -> Vec<usize> {
+    let max_length = *lengths.iter().max().unwrap_or(&0) as usize;
+    let mut length_counts: Vec<usize> = vec![0; max_length + 1];
+    for &l in lengths {
+        if l > 0 {
+            length_counts[l as usize] += 1;
+        }
+    }
+    let mut next_code: Vec<usize> = vec![0; max_length + 2];
+    let mut code = 0;
+    for bits in 1..=max_length {
+        code = (code + length_counts[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    lengths.iter().map(|&l| {
+        if l == 0 { return 0 }
+        let code = next_code[l as usize];
+        next_code[l as usize] += 1;
+        reverse_bits(code, l as usize)
+    }).collect()
+}
+
+fn reverse_bits(value: usize, bits: usize) -> usize {
+    (0..bits).fold(0, |reversed, i| (reversed << 1) | ((value >> i) & 1))
+}
+
+// Converts to an RFC1950 zlib stream.
 // See: https://datatracker.ietf.org/doc/html/rfc1950
-// In the simple uncompressed case, this boils down to adding a header and checksum.
+// This boils down to adding a header and checksum around the deflate stream.
 // Test output with: ruby -rzlib -e 'print Zlib::Inflate.new.inflate(STDIN.read)' < foo.zlib
 fn to_zlib_stream(input: &[u8]) -> Vec<u8> {
     // Header.
     let mut output = Vec::<u8>::new();
     output.push(0x78); // CMF byte. Bits 0-3=method, 4-7=info/window size. Method=8, Window size=7.
-    output.push(1); // FLG byte. Bits 0-4=fcheck, 5=fdict which we dont want so 0, 6-7=flevel where 0 means fastest.
+    output.push(0x9c); // FLG byte. Bits 0-4=fcheck, 5=fdict which we dont want so 0, 6-7=flevel where 2 means default.
 
     // Body.
     let deflated = to_deflate_stream(input);
@@ -61,7 +415,7 @@ fn to_zlib_stream(input: &[u8]) -> Vec<u8> {
 }
 
 // http://libpng.org/pub/png/spec/1.0/PNG-CRCAppendix.html
-fn crc(data: &[u8]) -> u32 {
+pub fn crc(data: &[u8]) -> u32 {
     // Make the CRC table first.
     let mut crc_table: [u32; 256] = [0; 256];
     for n in 0..256 {
@@ -97,6 +451,57 @@ fn append_msb_u16(vec: &mut Vec<u8>, value: u16) {
     vec.push((value & 0xff) as u8);
 }
 
+// Left-right, then Top-bottom, each pixel as R,G,B,A bytes.
+fn rgba_bytes(image_data: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::<u8>::with_capacity(image_data.len() * 4);
+    for pixel in image_data {
+        append_msb(&mut bytes, *pixel);
+    }
+    bytes
+}
+
+// Prepends each line with a filter type byte, and filters the line accordingly.
+// The filter is picked per line using the usual heuristic: whichever gives the smallest sum of
+// the bytes treated as signed, as small differences compress best.
+// https://www.w3.org/TR/png/#9Filters
+fn filtered_scanlines(raw: &[u8], stride: usize, bytes_per_pixel: usize) -> Vec<u8> {
+    if stride == 0 { return Vec::new() } // Zero-width images have no bytes to filter.
+    let mut output = Vec::<u8>::with_capacity(raw.len() + raw.len() / stride);
+    let blank_line: Vec<u8> = vec![0; stride];
+    let mut candidates: Vec<Vec<u8>> = vec![vec![0; stride]; 5];
+    for (y, line) in raw.chunks(stride).enumerate() {
+        let above = if y == 0 { &blank_line[..] } else { &raw[((y - 1) * stride)..(y * stride)] };
+        for (filter, candidate) in candidates.iter_mut().enumerate() {
+            for x in 0..line.len() {
+                let a = if x >= bytes_per_pixel { line[x - bytes_per_pixel] } else { 0 }; // Left.
+                let b = above[x]; // Up.
+                let c = if x >= bytes_per_pixel { above[x - bytes_per_pixel] } else { 0 }; // Up-left.
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[x] = line[x].wrapping_sub(predicted);
+            }
+        }
+        let cost = |candidate: &Vec<u8>| -> usize { candidate.iter().map(|&v| (v as i8).unsigned_abs() as usize).sum() };
+        let best = (0..5).min_by_key(|&f| cost(&candidates[f])).unwrap();
+        output.push(best as u8);
+        output.extend_from_slice(&candidates[best][..line.len()]);
+    }
+    output
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = (a as i16) + (b as i16) - (c as i16);
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
 // https://en.wikipedia.org/wiki/Portable_Network_Graphics#File_format
 pub fn png_data(width: usize, height: usize, image_data: &[u32]) -> Vec<u8> {
     let mut output = Vec::<u8>::new();
@@ -132,16 +537,7 @@ pub fn png_data(width: usize, height: usize, image_data: &[u32]) -> Vec<u8> {
     append_msb(&mut output, ihdr_crc);
 
     // Build image data.
-    // Left-right, then Top-bottom.
-    // Each line is prepended a filter type byte (0).
-    let mut idat_data = Vec::<u8>::new();
-    let mut data_iter = image_data.iter();
-    for _y in 0..height {
-        idat_data.push(0); // Filter.
-        for _x in 0..width {
-            append_msb(&mut idat_data, *data_iter.next().unwrap());
-        }
-    }
+    let idat_data = filtered_scanlines(&rgba_bytes(image_data), width * 4, 4);
     let compressed_idat_data = to_zlib_stream(&idat_data);
 
     // Build IDAT.
@@ -171,7 +567,12 @@ pub fn png_data(width: usize, height: usize, image_data: &[u32]) -> Vec<u8> {
 }
 
 // https://en.wikipedia.org/wiki/APNG#File_format
-pub fn apng_data(width: usize, height: usize, frames: &[Vec<u32>]) -> Vec<u8> {
+// Frames after the first only store the rectangle that changed since the previous frame.
+// Each is written whichever way compresses smallest: overwriting that rectangle, or blending over
+// it with the unchanged pixels made transparent (which only works if the changed pixels are opaque).
+// Delays are a fraction of a second per frame: (numerator, denominator). Loop count 0 means forever.
+pub fn apng_data(width: usize, height: usize, frames: &[Vec<u32>], delays: &[(u16, u16)], loop_count: u32) -> Vec<u8> {
+    assert!(delays.len() == frames.len(), "Need a delay for each frame!");
     let mut output = Vec::<u8>::new();
 
     // Header.
@@ -185,110 +586,329 @@ pub fn apng_data(width: usize, height: usize, frames: &[Vec<u32>]) -> Vec<u8> {
     output.push(0x0a); // Lf
 
     // Build IHDR.
-    let mut ihdr_type_and_data = Vec::<u8>::new();
-    ihdr_type_and_data.push(b'I');
-    ihdr_type_and_data.push(b'H');
-    ihdr_type_and_data.push(b'D');
-    ihdr_type_and_data.push(b'R');
-    append_msb(&mut ihdr_type_and_data, width as u32);
-    append_msb(&mut ihdr_type_and_data, height as u32);
-    ihdr_type_and_data.push(8); // 8bpp.
-    ihdr_type_and_data.push(6); // RGBA.
-    ihdr_type_and_data.push(0); // Compression method: zlib.
-    ihdr_type_and_data.push(0); // Filter method.
-    ihdr_type_and_data.push(0); // No interlace.
-    let ihdr_len = ihdr_type_and_data.len() - 4; // Minus the type.
-    let ihdr_crc = crc(&ihdr_type_and_data);
-    // Append IHDR to output.
-    append_msb(&mut output, ihdr_len as u32);
-    output.extend_from_slice(&ihdr_type_and_data);
-    append_msb(&mut output, ihdr_crc);
+    let mut ihdr = Vec::<u8>::new();
+    append_msb(&mut ihdr, width as u32);
+    append_msb(&mut ihdr, height as u32);
+    ihdr.push(8); // 8bpp.
+    ihdr.push(6); // RGBA.
+    ihdr.push(0); // Compression method: zlib.
+    ihdr.push(0); // Filter method.
+    ihdr.push(0); // No interlace.
+    append_chunk(&mut output, b"IHDR", &ihdr);
 
     // acTL: (just one)
     // https://wiki.mozilla.org/APNG_Specification#%60acTL%60:_The_Animation_Control_Chunk
-    let mut actl_type_and_data = Vec::<u8>::new();
-    actl_type_and_data.push(b'a');
-    actl_type_and_data.push(b'c');
-    actl_type_and_data.push(b'T');
-    actl_type_and_data.push(b'L');
-    append_msb(&mut actl_type_and_data, frames.len() as u32); // Number of frames.
-    append_msb(&mut actl_type_and_data, 0); // Number of times to loop, 0=infinite.
-    let actl_len = actl_type_and_data.len() - 4; // Minus the type.
-    let actl_crc = crc(&actl_type_and_data);
-    // Append acTL to output.
-    append_msb(&mut output, actl_len as u32);
-    output.extend_from_slice(&actl_type_and_data);
-    append_msb(&mut output, actl_crc);
+    let mut actl = Vec::<u8>::new();
+    append_msb(&mut actl, frames.len() as u32); // Number of frames.
+    append_msb(&mut actl, loop_count); // Number of times to loop, 0=infinite.
+    append_chunk(&mut output, b"acTL", &actl);
 
+    let mut sequence: u32 = 0;
     for (index, frame) in frames.iter().enumerate() {
+        let optimised = if index == 0 {
+            // The first frame is also the default image, so it must cover the whole canvas.
+            OptimisedFrame {
+                rect: FrameRect { x: 0, y: 0, width, height },
+                blend: BLEND_SOURCE,
+                data: to_zlib_stream(&filtered_scanlines(&rgba_bytes(frame), width * 4, 4)),
+            }
+        } else {
+            optimised_frame(&frames[index - 1], frame, width, height)
+        };
+
         // fcTL: (before each frame)
         // https://wiki.mozilla.org/APNG_Specification#%60fcTL%60:_The_Frame_Control_Chunk
-        let mut fctl_type_and_data = Vec::<u8>::new();
-        fctl_type_and_data.push(b'f');
-        fctl_type_and_data.push(b'c');
-        fctl_type_and_data.push(b'T');
-        fctl_type_and_data.push(b'L');
-        let fctl_sequence: u32 = if index == 0 { 0 } else { (index as u32) * 2 - 1 };
-        append_msb(&mut fctl_type_and_data, fctl_sequence); // Sequence number starting 0.
-        append_msb(&mut fctl_type_and_data, width as u32);
-        append_msb(&mut fctl_type_and_data, height as u32);
-        append_msb(&mut fctl_type_and_data, 0); // X-offset.
-        append_msb(&mut fctl_type_and_data, 0); // Y-offset.
-        append_msb_u16(&mut fctl_type_and_data, 20); // Delay numerator.
-        append_msb_u16(&mut fctl_type_and_data, 0); // Delay denominator. 0 means each value is 100ths of a second.
-        fctl_type_and_data.push(1); // Dispose operation. 1 means each frame gets a blank canvas.
-        fctl_type_and_data.push(0); // Blend operation. 0 means all components overwrite.
-        let fctl_len = fctl_type_and_data.len() - 4; // Minus the type.
-        let fctl_crc = crc(&fctl_type_and_data);
-        // Append fctl to output.
-        append_msb(&mut output, fctl_len as u32);
-        output.extend_from_slice(&fctl_type_and_data);
-        append_msb(&mut output, fctl_crc);
-
-        // Build image data.
-        // Left-right, then Top-bottom.
-        // Each line is prepended a filter type byte (0).
-        let mut idat_data = Vec::<u8>::new();
-        let mut data_iter = frame.iter();
-        for _y in 0..height {
-            idat_data.push(0); // Filter.
-            for _x in 0..width {
-                append_msb(&mut idat_data, *data_iter.next().unwrap());
-            }
-        }
-        let compressed_idat_data = to_zlib_stream(&idat_data);
+        let mut fctl = Vec::<u8>::new();
+        append_msb(&mut fctl, sequence); // Sequence number starting 0, shared with fdAT.
+        sequence += 1;
+        append_msb(&mut fctl, optimised.rect.width as u32);
+        append_msb(&mut fctl, optimised.rect.height as u32);
+        append_msb(&mut fctl, optimised.rect.x as u32); // X-offset.
+        append_msb(&mut fctl, optimised.rect.y as u32); // Y-offset.
+        let (delay_numerator, delay_denominator) = delays[index];
+        append_msb_u16(&mut fctl, delay_numerator); // Delay numerator.
+        append_msb_u16(&mut fctl, delay_denominator); // Delay denominator. 0 would mean 100ths of a second.
+        fctl.push(DISPOSE_NONE); // Dispose operation. Leaving each frame in place lets the next one only store changes.
+        fctl.push(optimised.blend); // Blend operation.
+        append_chunk(&mut output, b"fcTL", &fctl);
 
-        // Build IDAT (first) / fdAT (subsequent frames).
-        let mut idat_type_and_data = Vec::<u8>::new();
+        // IDAT (first) / fdAT (subsequent frames).
         if index == 0 {
-            idat_type_and_data.push(b'I');
-            idat_type_and_data.push(b'D');
-            idat_type_and_data.push(b'A');
-            idat_type_and_data.push(b'T');
+            append_chunk(&mut output, b"IDAT", &optimised.data);
         } else {
-            idat_type_and_data.push(b'f');
-            idat_type_and_data.push(b'd');
-            idat_type_and_data.push(b'A');
-            idat_type_and_data.push(b'T');
-            append_msb(&mut idat_type_and_data, (index as u32) * 2);
+            let mut fdat = Vec::<u8>::with_capacity(4 + optimised.data.len());
+            append_msb(&mut fdat, sequence);
+            sequence += 1;
+            fdat.extend_from_slice(&optimised.data);
+            append_chunk(&mut output, b"fdAT", &fdat);
         }
-        idat_type_and_data.extend_from_slice(&compressed_idat_data);
-        let idat_len = idat_type_and_data.len() - 4; // Minus the type.
-        let idat_crc = crc(&idat_type_and_data);
-        // Append IDAT to output.
-        append_msb(&mut output, idat_len as u32);
-        output.extend_from_slice(&idat_type_and_data);
-        append_msb(&mut output, idat_crc);
     }
 
-    // IEND (no data).
-    append_msb(&mut output, 0); // Length.
-    output.push(b'I'); // Type.
-    output.push(b'E');
-    output.push(b'N');
-    output.push(b'D');
-    let iend_crc = crc(b"IEND");
-    append_msb(&mut output, iend_crc);
+    append_chunk(&mut output, b"IEND", &[]);
+    output
+}
 
+const DISPOSE_NONE: u8 = 0;
+const BLEND_SOURCE: u8 = 0; // All components overwrite.
+const BLEND_OVER: u8 = 1; // Alpha blended over the canvas.
+
+struct FrameRect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+struct OptimisedFrame {
+    rect: FrameRect,
+    blend: u8,
+    data: Vec<u8>, // Compressed.
+}
+
+// Works out the smallest way to draw the frame, given the previous frame is still on the canvas.
+fn optimised_frame(previous: &[u32], frame: &[u32], width: usize, height: usize) -> OptimisedFrame {
+    // A frame that doesn't change anything still needs a pixel, so redraw the top left.
+    let rect = changed_rect(previous, frame, width, height).unwrap_or(FrameRect { x: 0, y: 0, width: 1, height: 1 });
+    let mut source: Vec<u32> = Vec::with_capacity(rect.width * rect.height);
+    let mut over: Vec<u32> = Vec::with_capacity(rect.width * rect.height);
+    let mut can_blend_over = true;
+    for y in rect.y..(rect.y + rect.height) {
+        for x in rect.x..(rect.x + rect.width) {
+            let i = y * width + x;
+            source.push(frame[i]);
+            if frame[i] == previous[i] {
+                over.push(0); // Transparent, so the previous pixel shows through.
+            } else {
+                can_blend_over &= frame[i] & 0xff == 0xff;
+                over.push(frame[i]);
+            }
+        }
+    }
+    let compress = |pixels: &[u32]| to_zlib_stream(&filtered_scanlines(&rgba_bytes(pixels), rect.width * 4, 4));
+    let source_data = compress(&source);
+    if can_blend_over {
+        let over_data = compress(&over);
+        if over_data.len() < source_data.len() {
+            return OptimisedFrame { rect, blend: BLEND_OVER, data: over_data };
+        }
+    }
+    OptimisedFrame { rect, blend: BLEND_SOURCE, data: source_data }
+}
+
+// The bounding box of pixels that differ, or None if the frames are identical.
+fn changed_rect(previous: &[u32], frame: &[u32], width: usize, height: usize) -> Option<FrameRect> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if previous[y * width + x] == frame[y * width + x] { continue }
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x > max_x { return None }
+    Some(FrameRect { x: min_x, y: min_y, width: max_x - min_x + 1, height: max_y - min_y + 1 })
+}
+
+// Writes a palette-based PNG, which keeps the original colour indexes and is much smaller than RGBA.
+// The bit depth is the smallest (1, 2, 4 or 8) that fits the palette, which is given as RGBA.
+// Alpha goes in a tRNS chunk, only as far as the last entry that isn't opaque.
+// https://www.w3.org/TR/png/#11PLTE
+pub fn indexed_png_data(width: usize, height: usize, indexes: &[u8], palette: &[u32]) -> Vec<u8> {
+    assert!(!palette.is_empty() && palette.len() <= 256, "Palette must have 1-256 entries!");
+    let bit_depth: usize = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let mut output = Vec::<u8>::new();
+    output.extend_from_slice(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+    let mut ihdr = Vec::<u8>::new();
+    append_msb(&mut ihdr, width as u32);
+    append_msb(&mut ihdr, height as u32);
+    ihdr.push(bit_depth as u8);
+    ihdr.push(3); // Indexed colour.
+    ihdr.push(0); // Compression method: zlib.
+    ihdr.push(0); // Filter method.
+    ihdr.push(0); // No interlace.
+    append_chunk(&mut output, b"IHDR", &ihdr);
+
+    let mut plte = Vec::<u8>::new();
+    for colour in palette {
+        plte.push((colour >> 24) as u8);
+        plte.push((colour >> 16) as u8);
+        plte.push((colour >> 8) as u8);
+    }
+    append_chunk(&mut output, b"PLTE", &plte);
+
+    if let Some(last_translucent) = palette.iter().rposition(|c| c & 0xff != 0xff) {
+        let trns: Vec<u8> = palette[..=last_translucent].iter().map(|c| (c & 0xff) as u8).collect();
+        append_chunk(&mut output, b"tRNS", &trns);
+    }
+
+    // Pack the pixels, leftmost pixel in the high bits. Each line starts on a byte boundary.
+    let stride = (width * bit_depth).div_ceil(8);
+    let mut packed = vec![0u8; stride * height];
+    for (y, row) in indexes.chunks_exact(width.max(1)).enumerate() { // Zero-width images have no indexes to pack.
+        for (x, &index) in row.iter().enumerate() {
+            assert!((index as usize) < palette.len(), "Index {} is outside the palette!", index);
+            let bit = x * bit_depth;
+            packed[y * stride + bit / 8] |= index << (8 - bit_depth - (bit % 8));
+        }
+    }
+    let idat = to_zlib_stream(&filtered_scanlines(&packed, stride, 1));
+    append_chunk(&mut output, b"IDAT", &idat);
+    append_chunk(&mut output, b"IEND", &[]);
     output
 }
+
+// Reads a PNG back in as RGBA, for turning edited images back into resources.
+// Supports what this tool writes, and what image editors typically save: 8 bit RGB/RGBA, and palettes of any bit depth.
+pub fn rgbas_from_png_data(data: &[u8]) -> (usize, usize, Vec<u32>) {
+    assert!(data.len() > 8 && data[..8] == [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a], "Not a PNG!");
+    let mut position = 8;
+    let (mut width, mut height, mut bit_depth, mut colour_type) = (0, 0, 0, 0);
+    let mut palette: Vec<u32> = Vec::new();
+    let mut idat: Vec<u8> = Vec::new();
+    while position + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[position..(position + 4)].try_into().unwrap()) as usize;
+        let chunk_type = &data[(position + 4)..(position + 8)];
+        let chunk = &data[(position + 8)..(position + 8 + length)];
+        match chunk_type {
+            b"IHDR" => {
+                width = u32::from_be_bytes(chunk[0..4].try_into().unwrap()) as usize;
+                height = u32::from_be_bytes(chunk[4..8].try_into().unwrap()) as usize;
+                bit_depth = chunk[8] as usize;
+                colour_type = chunk[9];
+                assert!(chunk[12] == 0, "Interlaced PNGs aren't supported!");
+            }
+            b"PLTE" => palette = chunk.chunks_exact(3).map(|c| ((c[0] as u32) << 24) | ((c[1] as u32) << 16) | ((c[2] as u32) << 8) | 0xff).collect(),
+            b"tRNS" => {
+                for (entry, alpha) in palette.iter_mut().zip(chunk) {
+                    *entry = (*entry & 0xffffff00) | (*alpha as u32);
+                }
+            }
+            b"IDAT" => idat.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        position += 12 + length;
+    }
+    let (bits_per_pixel, bytes_per_pixel) = match (colour_type, bit_depth) {
+        (2, 8) => (24, 3),
+        (6, 8) => (32, 4),
+        (3, 1 | 2 | 4 | 8) => (bit_depth, 1),
+        _ => panic!("Unsupported PNG colour type {} at bit depth {}!", colour_type, bit_depth),
+    };
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let idat = inflate::inflate_zlib(&idat).unwrap_or_else(|e| panic!("PNG image data is corrupt: {}", e));
+    let raw = unfiltered_scanlines(&idat, stride, height, bytes_per_pixel);
+    let mut rgbas: Vec<u32> = Vec::with_capacity(width * height);
+    for line in raw.chunks_exact(stride) {
+        for x in 0..width {
+            let rgba = match colour_type {
+                2 => u32::from_be_bytes([line[x * 3], line[x * 3 + 1], line[x * 3 + 2], 0xff]),
+                6 => u32::from_be_bytes(line[(x * 4)..(x * 4 + 4)].try_into().unwrap()),
+                _ => {
+                    let bit = x * bit_depth;
+                    let index = (line[bit / 8] >> (8 - bit_depth - (bit % 8))) & ((1 << bit_depth) - 1) as u8;
+                    *palette.get(index as usize).expect("PNG palette index out of range!")
+                }
+            };
+            rgbas.push(rgba);
+        }
+    }
+    (width, height, rgbas)
+}
+
+// The inverse of filtered_scanlines.
+fn unfiltered_scanlines(filtered: &[u8], stride: usize, height: usize, bytes_per_pixel: usize) -> Vec<u8> {
+    assert!(filtered.len() >= (stride + 1) * height, "PNG image data is too short!");
+    let mut raw: Vec<u8> = vec![0; stride * height];
+    for y in 0..height {
+        let filter = filtered[y * (stride + 1)];
+        let line = &filtered[(y * (stride + 1) + 1)..((y + 1) * (stride + 1))];
+        for x in 0..stride {
+            let a = if x >= bytes_per_pixel { raw[y * stride + x - bytes_per_pixel] } else { 0 }; // Left.
+            let b = if y > 0 { raw[(y - 1) * stride + x] } else { 0 }; // Up.
+            let c = if x >= bytes_per_pixel && y > 0 { raw[(y - 1) * stride + x - bytes_per_pixel] } else { 0 }; // Up-left.
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => panic!("Unknown PNG filter type: {}", filter),
+            };
+            raw[y * stride + x] = line[x].wrapping_add(predicted);
+        }
+    }
+    raw
+}
+
+// Appends length, type, data, and the CRC of the type and data.
+fn append_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    append_msb(output, data.len() as u32);
+    let mut type_and_data = Vec::<u8>::with_capacity(4 + data.len());
+    type_and_data.extend_from_slice(chunk_type);
+    type_and_data.extend_from_slice(data);
+    output.extend_from_slice(&type_and_data);
+    append_msb(output, crc(&type_and_data));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_assigns_canonical_codes() {
+        // The example from RFC1951 3.2.2, with the codes bit-reversed.
+        let lengths: Vec<u8> = vec![3, 3, 3, 3, 3, 2, 4, 4];
+        let expected: Vec<usize> = [0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]
+            .iter().zip(&lengths).map(|(&c, &l)| reverse_bits(c, l as usize)).collect();
+        assert_eq!(canonical_codes(&lengths), expected);
+    }
+
+    #[test]
+    fn it_picks_the_smallest_bit_depth() {
+        let palette = &crate::palette::PALETTE;
+        let bit_depth = |count: usize| indexed_png_data(3, 1, &[0, 1, 0], &palette[..count])[24];
+        assert_eq!(bit_depth(2), 1);
+        assert_eq!(bit_depth(4), 2);
+        assert_eq!(bit_depth(16), 4);
+        assert_eq!(bit_depth(17), 8);
+    }
+
+    #[test]
+    fn it_finds_the_changed_rect() {
+        let previous: Vec<u32> = vec![0; 16];
+        let mut frame = previous.clone();
+        frame[4 + 1] = 0xff;
+        frame[8 + 2] = 0xff;
+        let rect = changed_rect(&previous, &frame, 4, 4).unwrap();
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (1, 1, 2, 2));
+        assert!(changed_rect(&previous, &previous, 4, 4).is_none());
+    }
+
+    #[test]
+    fn it_writes_empty_images() {
+        for (width, height) in [(0, 3), (4, 0), (0, 0)] {
+            assert!(png_data(width, height, &[]).ends_with(b"IEND\xae\x42\x60\x82"));
+            assert!(indexed_png_data(width, height, &[], &crate::palette::PALETTE).ends_with(b"IEND\xae\x42\x60\x82"));
+        }
+    }
+
+    #[test]
+    fn it_round_trips_through_inflate() {
+        let mut data: Vec<u8> = b"Sierra On-Line presents... ".repeat(300);
+        data.extend((0..5000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+        assert_eq!(inflate::inflate_zlib(&to_zlib_stream(&data)).unwrap(), data);
+        let pixels: Vec<u8> = (0..(7 * 5)).map(|i| (i % 17) as u8).collect();
+        let (width, height, rgbas) = rgbas_from_png_data(&indexed_png_data(7, 5, &pixels, &crate::palette::PALETTE));
+        assert_eq!((width, height), (7, 5));
+        assert!(rgbas.iter().zip(&pixels).all(|(rgba, &p)| *rgba == crate::palette::PALETTE[p as usize]));
+    }
+}
@@ -3,10 +3,13 @@
 // A 'dither double' is where both nibbles contain an EGA palette index.
 
 use crate::view::{Loop, Cel};
+use crate::sprite_sheet::SpriteSheet;
 use crate::png;
+use crate::gif;
 use crate::picture;
 use crate::palette;
 use crate::xbrz;
+use crate::timing::{self, Timing};
 
 // The game is originally rendered at 320x200 on a 4:3 screen, so pixels are 1.2x higher than wide.
 // Resizing at 5w x 6h preserves this ratio.
@@ -14,6 +17,25 @@ const WIDTH_MULTIPLIER: usize = 5;
 const HEIGHT_MULTIPLIER: usize = 6;
 const USE_XBRZ: bool = true;
 
+// How the two colours of a picture's dither doubles are shown.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dither {
+    Average, // Blend the two colours, which is roughly how it looked on a CRT.
+    Exact, // Checkerboard at the original resolution, then scale up, exactly like the EGA screen.
+    ExactAfterScaling, // Scale up, then checkerboard at the output resolution, for a finer dither. Doesn't use xBRZ.
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Dither> {
+        match name {
+            "average" => Some(Dither::Average),
+            "exact" => Some(Dither::Exact),
+            "exact-after-scaling" => Some(Dither::ExactAfterScaling),
+            _ => None,
+        }
+    }
+}
+
 // It's eligible to be an animation even if sizes are different.
 // Padding is added to the top and right, which seems to align cels nicely on space quest.
 pub fn is_animation(viewloop: &Loop) -> bool {
@@ -21,37 +43,150 @@ pub fn is_animation(viewloop: &Loop) -> bool {
 }
 
 // This assumes it's normal pixels, not dither-doubles.
-pub fn apng_from_loop(viewloop: &Loop) -> Vec<u8> {
+// Delays are in ticks, which APNG can store exactly as a fraction.
+pub fn apng_from_loop(viewloop: &Loop, timing: &Timing, threads: usize) -> Vec<u8> {
     // Get max height.
     let width = viewloop.cels.iter().map(|c| c.width).max().unwrap();
     let height = viewloop.cels.iter().map(|c| c.height).max().unwrap();
     let frames: Vec<Vec<u32>> = viewloop.cels.iter()
         .map(|c| pad_cel(c, width, height))
-        .map(|c| scaled_rgbas_from_cel(&c, false))
+        .map(|c| scaled_rgbas_from_cel(&c, false, threads))
         .collect();
+    let delays: Vec<(u16, u16)> = (0..frames.len()).map(|i| (timing.ticks_for_cel(i), timing::TICKS_PER_SECOND)).collect();
     png::apng_data(
         width * WIDTH_MULTIPLIER,
         height * HEIGHT_MULTIPLIER,
-        &frames)
+        &frames,
+        &delays,
+        timing.loop_count)
+}
+
+// GIFs are limited to a 256 colour palette, so this scales the palette indexes up without xBRZ.
+// GIF delays are in 100ths of a second, so ticks are rounded to the nearest.
+pub fn gif_from_loop(viewloop: &Loop, timing: &Timing) -> Vec<u8> {
+    let width = viewloop.cels.iter().map(|c| c.width).max().unwrap();
+    let height = viewloop.cels.iter().map(|c| c.height).max().unwrap();
+    let frames: Vec<Vec<u8>> = viewloop.cels.iter()
+        .map(|c| scaled_cel_nearest_neighbour(&pad_cel(c, width, height)).pixels)
+        .collect();
+    let ticks_per_second = timing::TICKS_PER_SECOND as u32;
+    let delays: Vec<u16> = (0..frames.len())
+        .map(|i| ((timing.ticks_for_cel(i) as u32 * 100 + ticks_per_second / 2) / ticks_per_second) as u16)
+        .collect();
+    gif::gif_data(
+        width * WIDTH_MULTIPLIER,
+        height * HEIGHT_MULTIPLIER,
+        &frames,
+        &palette::PALETTE,
+        Some(palette::TRANSPARENT),
+        &delays,
+        timing.loop_count)
 }
 
-pub fn png_from_cel(cel: &Cel) -> Vec<u8> {
+pub fn png_from_cel(cel: &Cel, threads: usize) -> Vec<u8> {
     png::png_data(
         cel.width * WIDTH_MULTIPLIER,
         cel.height * HEIGHT_MULTIPLIER,
-        &scaled_rgbas_from_cel(cel, false))
+        &scaled_rgbas_from_cel(cel, false, threads))
 }
 
-pub fn png_from_picture(picture: &picture::Picture) -> Vec<u8> {
+pub fn png_from_picture(picture: &picture::Picture, dither: Dither, threads: usize) -> Vec<u8> {
+    png::png_data(
+        picture::WIDTH * WIDTH_MULTIPLIER,
+        picture::HEIGHT * HEIGHT_MULTIPLIER,
+        &scaled_rgbas_from_picture(picture, dither, threads))
+}
+
+fn scaled_rgbas_from_picture(picture: &picture::Picture, dither: Dither, threads: usize) -> Vec<u32> {
     let cel: Cel = Cel {
         width: picture::WIDTH,
         height: picture::HEIGHT,
         pixels: picture.picture.clone(),
+        x_placement: 0,
+        y_placement: 0,
+    };
+    match dither {
+        Dither::Average => scaled_rgbas_from_cel(&cel, true, threads),
+        Dither::Exact => scaled_rgbas_from_cel(&dithered_cel(&cel), false, threads),
+        Dither::ExactAfterScaling => {
+            dithered_cel(&scaled_cel_nearest_neighbour(&cel)).pixels.iter()
+                .map(|p| rgba_from_indexed_colour(*p, false))
+                .collect()
+        }
+    }
+}
+
+// Priority and control screens are plain 0-15 values, so they're shown using the EGA palette.
+pub fn png_from_screen(screen: &[u8], threads: usize) -> Vec<u8> {
+    let cel: Cel = Cel {
+        width: picture::WIDTH,
+        height: picture::HEIGHT,
+        pixels: screen.to_vec(),
+        x_placement: 0,
+        y_placement: 0,
     };
     png::png_data(
         cel.width * WIDTH_MULTIPLIER,
         cel.height * HEIGHT_MULTIPLIER,
-        &scaled_rgbas_from_cel(&cel, true))
+        &scaled_rgbas_from_cel(&cel, false, threads))
+}
+
+// The indexed variants are unscaled and keep the EGA palette indexes, for pipelines that need the original colours.
+pub fn indexed_png_from_cel(cel: &Cel) -> Vec<u8> {
+    indexed_png_from_pixels(cel.width, cel.height, &cel.pixels)
+}
+
+// Pictures are dithered exactly, so each pixel is one of the 16 EGA colours like the real screen.
+pub fn indexed_png_from_picture(picture: &picture::Picture) -> Vec<u8> {
+    let cel: Cel = Cel {
+        width: picture::WIDTH,
+        height: picture::HEIGHT,
+        pixels: picture.picture.clone(),
+        x_placement: 0,
+        y_placement: 0,
+    };
+    let dithered = dithered_cel(&cel);
+    indexed_png_from_pixels(dithered.width, dithered.height, &dithered.pixels)
+}
+
+pub fn indexed_png_from_sprite_sheet(sheet: &SpriteSheet) -> Vec<u8> {
+    indexed_png_from_pixels(sheet.width, sheet.height, &sheet.pixels)
+}
+
+pub fn indexed_png_from_screen(screen: &[u8]) -> Vec<u8> {
+    indexed_png_from_pixels(picture::WIDTH, picture::HEIGHT, screen)
+}
+
+// The palette is trimmed to the highest index used, so a cel without transparency fits in 4 bits.
+fn indexed_png_from_pixels(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let count = pixels.iter().max().map_or(1, |&m| (m as usize) + 1);
+    png::indexed_png_data(width, height, pixels, &palette::PALETTE[..count])
+}
+
+// Converts a cel of dither doubles to normal pixels, picking one of the two colours in a checkerboard.
+// x+y parity picks the nibble, as the EGA interpreter did.
+fn dithered_cel(cel: &Cel) -> Cel {
+    let pixels: Vec<u8> = cel.pixels.iter().enumerate().map(|(i, p)| {
+        let x = i % cel.width;
+        let y = i / cel.width;
+        if (x + y) & 1 == 0 { p & 0xf } else { p >> 4 }
+    }).collect();
+    Cel { width: cel.width, height: cel.height, pixels, ..*cel }
+}
+
+// Scales up the palette indices themselves, rather than the colours.
+fn scaled_cel_nearest_neighbour(cel: &Cel) -> Cel {
+    let mut pixels: Vec<u8> = Vec::with_capacity(cel.width * cel.height * WIDTH_MULTIPLIER * HEIGHT_MULTIPLIER);
+    for row in cel.pixels.chunks_exact(cel.width) {
+        for _ in 0..HEIGHT_MULTIPLIER {
+            for p in row {
+                for _ in 0..WIDTH_MULTIPLIER {
+                    pixels.push(*p);
+                }
+            }
+        }
+    }
+    Cel { width: cel.width * WIDTH_MULTIPLIER, height: cel.height * HEIGHT_MULTIPLIER, pixels, ..*cel }
 }
 
 // Increase the width/height of a cel.
@@ -71,13 +206,13 @@ fn pad_cel(cel: &Cel, width: usize, height: usize) -> Cel {
             pixels.push(palette::TRANSPARENT);
         }
     }
-    Cel { width, height, pixels }
+    Cel { width, height, pixels, ..*cel }
 }
 
-// This converts an unscaled cel to scaled rgbas.
-fn scaled_rgbas_from_cel(cel: &Cel, is_dither_double: bool) -> Vec<u32> {
+// This converts an unscaled cel to scaled rgbas, with xBRZ using up to the given number of threads.
+fn scaled_rgbas_from_cel(cel: &Cel, is_dither_double: bool, threads: usize) -> Vec<u32> {
     if USE_XBRZ {
-        scaled_rgbas_from_cel_xbrz(cel, is_dither_double)
+        scaled_rgbas_from_cel_xbrz(cel, is_dither_double, threads)
     } else {
         scaled_rgbas_from_cel_nearest_neighbour(cel, is_dither_double)
     }
@@ -199,11 +334,11 @@ fn scaled_rgbas_from_cel_crt_3(cel: &Cel, is_dither_double: bool) -> Vec<u32> {
     rgbas
 }
 
-fn scaled_rgbas_from_cel_xbrz(cel: &Cel, is_dither_double: bool) -> Vec<u32> {
+fn scaled_rgbas_from_cel_xbrz(cel: &Cel, is_dither_double: bool, threads: usize) -> Vec<u32> {
     // Scale up using xbrz:
     let unscaled_rgbas: Vec<u32> = cel.pixels.iter().map(|p| rgba_from_indexed_colour(*p, is_dither_double)).collect();
     let bigger_dimension = HEIGHT_MULTIPLIER.max(WIDTH_MULTIPLIER);
-    let scaled_square = xbrz::scale(bigger_dimension as u8, &unscaled_rgbas, cel.width as u32, cel.height as u32);
+    let scaled_square = xbrz::scale(bigger_dimension as u8, &unscaled_rgbas, cel.width as u32, cel.height as u32, threads);
     if WIDTH_MULTIPLIER == HEIGHT_MULTIPLIER {
         return scaled_square
     }
@@ -238,7 +373,7 @@ fn interpolate_rgba(x: u32, y: u32) -> u32 {
 }
 
 // This supports both cels (no dither doubles) and pics (dither doubles - each nibble has a colour).
-fn rgba_from_indexed_colour(index: u8, is_dither_double: bool) -> u32 {
+pub fn rgba_from_indexed_colour(index: u8, is_dither_double: bool) -> u32 {
     if is_dither_double {
         let index_a = index & 0xf;
         let index_b = index >> 4;
@@ -253,3 +388,37 @@ fn rgba_from_indexed_colour(index: u8, is_dither_double: bool) -> u32 {
         palette::PALETTE[index as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_dithers_with_a_checkerboard() {
+        let cel = Cel { width: 2, height: 2, pixels: vec![0x1e; 4], x_placement: 0, y_placement: 0 };
+        assert_eq!(dithered_cel(&cel).pixels, vec![0xe, 0x1, 0x1, 0xe]); // Even x+y takes the low nibble.
+    }
+
+    #[test]
+    fn it_parses_dither_names() {
+        assert!(matches!(Dither::from_name("average"), Some(Dither::Average)));
+        assert!(matches!(Dither::from_name("exact"), Some(Dither::Exact)));
+        assert!(matches!(Dither::from_name("exact-after-scaling"), Some(Dither::ExactAfterScaling)));
+        assert!(Dither::from_name("Exact").is_none());
+    }
+
+    #[test]
+    fn it_dithers_each_output_pixel_after_scaling() {
+        let mut picture = picture::Picture::blank();
+        picture.picture[0] = 0x1e;
+        let rgbas = scaled_rgbas_from_picture(&picture, Dither::ExactAfterScaling, 1);
+        let stride = picture::WIDTH * WIDTH_MULTIPLIER;
+        assert_eq!(rgbas.len(), stride * picture::HEIGHT * HEIGHT_MULTIPLIER);
+        for y in 0..HEIGHT_MULTIPLIER {
+            for x in 0..WIDTH_MULTIPLIER {
+                let index = if (x + y) & 1 == 0 { 0xe } else { 0x1 };
+                assert_eq!(rgbas[y * stride + x], palette::PALETTE[index], "at {},{}", x, y);
+            }
+        }
+    }
+}
@@ -1,21 +1,53 @@
-// This is responsible for loading the resource.00x files into memory.
+// This is responsible for reading from the resource.00x files, and any loose patch files, from a source.
+// Files are only opened when a resource in them is first wanted, and only the bytes for that resource are read,
+// so pulling one view out of a big multi-disk game doesn't load every disk.
 
+use crate::resource_source::{self, Source, Volume};
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 pub struct Files {
-    pub files: HashMap<usize, Vec<u8>>,
+    source: Box<dyn Source>,
+    opened: Mutex<HashMap<String, Box<dyn Volume>>>, // By file name, eg resource.001 or view.123.
 }
 
 impl Files {
-    pub fn read(path: &str) -> Files {
-        let mut files: HashMap<usize, Vec<u8>> = HashMap::new();
-        for i in 1..999 {
-            let vol_path = format!("{}/resource.{:03}", path, i);
-            let exists = std::fs::exists(&vol_path).unwrap();
-            if !exists { break }
-            let content = std::fs::read(&vol_path).unwrap();
-            files.insert(i, content);
+    pub fn open(path: &str) -> Files {
+        Files::new(resource_source::open(path))
+    }
+
+    pub fn new(source: Box<dyn Source>) -> Files {
+        Files { source, opened: Mutex::new(HashMap::new()) }
+    }
+
+    // Gives None if the file is missing, or too short.
+    pub fn read_at(&self, name: &str, offset: usize, length: usize) -> Option<Vec<u8>> {
+        self.with_volume(name, |volume| volume.read_at(offset, length))?
+    }
+
+    pub fn size(&self, name: &str) -> Option<usize> {
+        self.with_volume(name, |volume| volume.size())
+    }
+
+    fn with_volume<T>(&self, name: &str, f: impl FnOnce(&mut dyn Volume) -> T) -> Option<T> {
+        let mut opened = self.opened.lock().unwrap();
+        if !opened.contains_key(name) {
+            opened.insert(name.to_string(), self.source.open(name)?);
         }
-        Files { files }
+        Some(f(opened.get_mut(name)?.as_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resource_reader::{self, tests::sample_game};
+
+    #[test]
+    fn it_opens_volumes_lazily() {
+        let (map, files) = sample_game();
+        assert_eq!(files.opened.lock().unwrap().len(), 0);
+        let resource = resource_reader::read(&map.entries[0], &files);
+        assert!(!resource.is_empty());
+        assert_eq!(files.opened.lock().unwrap().len(), 1);
     }
 }
@@ -1,49 +1,236 @@
 // This is responsible for reading the resources out of the files.
 // https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/SCI0_resources
 // https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/Decompression_algorithms
-// Note this won't support SCI0.1 games because method 2 = COMP3: https://sciwiki.sierrahelp.com/index.php/Sierra_SCI_Release_List#SCI0.1
+// The generation (see version.rs) decides the header layout and what the methods mean, eg SCI01 games use method 2 for COMP3, which isn't supported yet: https://sciwiki.sierrahelp.com/index.php/Sierra_SCI_Release_List#SCI0.1
 
 use crate::resource_files::Files;
-use crate::map::Entry;
+use crate::map::{Entry, Location, Source};
 use crate::lzw;
 use crate::huffman;
+use crate::version::{Compression, Generation};
 
-const METHOD_UNCOMPRESSED: usize = 0;
-const METHOD_LZW: usize = 1;
-const METHOD_HUFFMAN: usize = 2;
+pub const METHOD_UNCOMPRESSED: usize = 0;
+pub const METHOD_LZW: usize = 1;
+pub const METHOD_HUFFMAN: usize = 2;
+pub const HEADER_SIZE: usize = 8; // For SCI0, which is what the writer writes.
+
+// The header before each resource in a volume.
+pub struct Header {
+    pub id: usize,
+    pub compressed_size: usize, // Actual size of the content after the header.
+    pub decompressed_size: usize,
+    pub method: usize,
+}
 
 pub fn read(entry: &Entry, files: &Files) -> Vec<u8> {
-    // Get the data from the appropriate file:
-    let file = files.files.get(&entry.file).unwrap();
-    let header_onwards = &file[entry.offset..];
-    let content_onwards = &header_onwards[8..];
+    read_with_header(entry, files).1
+}
 
-    // Parse the header:
-    let id = (header_onwards[0] as usize) + ((header_onwards[1] as usize) << 8);
-    let compressed_size  = (header_onwards[2] as usize) + ((header_onwards[3] as usize) << 8);
-    let decompressed_size = (header_onwards[4] as usize) + ((header_onwards[5] as usize) << 8);
-    let method = (header_onwards[6] as usize) + ((header_onwards[7] as usize) << 8);
+// Panics if no copy can be read.
+pub fn read_with_header(entry: &Entry, files: &Files) -> (Header, Vec<u8>) {
+    let (_, header, data) = read_first_copy(entry, files)
+        .unwrap_or_else(|e| panic!("Couldn't read {:?} {}: {}", entry.resource_type, entry.resource_number, e));
+    (header, data)
+}
 
-    // Use the header to get the maybe-compressed content of correct length:
-    if id != entry.id {
-        panic!("Id for resource data doesn't match!");
+// Tries each copy in turn, so a missing disk or bad floppy dump doesn't matter if another disk has the resource.
+// Gives the copy that was used, or why none of them could be.
+pub fn read_first_copy(entry: &Entry, files: &Files) -> Result<(Location, Header, Vec<u8>), String> {
+    let mut errors: Vec<String> = Vec::new();
+    for location in entry.locations() {
+        match read_copy(entry, files, location) {
+            Ok((header, data)) => {
+                if !errors.is_empty() {
+                    println!("Using the copy of {:?} {} in {}, since: {}", entry.resource_type, entry.resource_number, location.name(), errors.join(", "));
+                }
+                return Ok((location, header, data))
+            }
+            Err(error) => errors.push(format!("{} {}", location.name(), error)),
+        }
     }
-    let actual_compressed_size = compressed_size - 4; // Compressed size actually starts counting at the record position of decompressed_size.
-    let content = &content_onwards[..actual_compressed_size]; // Maybe-compressed content.
+    Err(errors.join(", "))
+}
+
+// The header and content exactly as stored in the volume, from the first copy that's all there,
+// even if it can't be decompressed. Patch files have none.
+pub fn read_stored(entry: &Entry, files: &Files) -> Option<Vec<u8>> {
+    if entry.source != Source::Volume { return None }
+    let header_size = entry.generation.header_size();
+    entry.locations().into_iter().find_map(|location| {
+        let data = files.read_at(&location.name(), location.offset, header_size)?;
+        let header = Header::parse_sizes(&data, entry.generation).ok()?;
+        files.read_at(&location.name(), location.offset, header_size + header.compressed_size)
+    })
+}
+
+// Reads every copy, for checking them against each other.
+pub fn read_copies(entry: &Entry, files: &Files) -> Vec<(Location, Result<Vec<u8>, String>)> {
+    entry.locations().into_iter().map(|l| (l, read_copy(entry, files, l).map(|(_, data)| data))).collect()
+}
+
+// The copies that read but don't match the first one that did, paired with that first one, to expose bad floppy dumps.
+pub fn differing_copies(copies: &[(Location, Result<Vec<u8>, String>)]) -> Vec<(Location, Location)> {
+    let readable: Vec<(Location, &Vec<u8>)> = copies.iter().filter_map(|(l, r)| Some((*l, r.as_ref().ok()?))).collect();
+    let Some(&(first_location, first)) = readable.first() else { return Vec::new() };
+    readable[1..].iter().filter(|(_, data)| *data != first).map(|&(location, _)| (first_location, location)).collect()
+}
+
+// Reads one copy. Patch files don't have a header, so this makes one up for them.
+pub fn read_copy(entry: &Entry, files: &Files, location: Location) -> Result<(Header, Vec<u8>), String> {
+    let (name, header, content_offset) = match &entry.source {
+        Source::Volume => {
+            let name = location.name();
+            let header_size = entry.generation.header_size();
+            let data = files.read_at(&name, location.offset, header_size).ok_or("is missing or too short")?;
+            let header = Header::parse(&data, entry.generation)?;
+            if header.id != entry.id { return Err("has a different id to the map".to_string()) }
+            (name, header, location.offset + header_size)
+        }
+        Source::Patch(name) => {
+            let size = files.size(name).ok_or("is missing")? - location.offset;
+            let header = Header { id: entry.id, compressed_size: size, decompressed_size: size, method: METHOD_UNCOMPRESSED };
+            (name.clone(), header, location.offset)
+        }
+    };
+    let content = files.read_at(&name, content_offset, header.compressed_size).ok_or("is too short")?; // Maybe-compressed content.
 
     // Decompress it if necessary:
-    decompress(content, decompressed_size, method)
-}
-
-fn decompress(content: &[u8], decompressed_size: usize, method: usize) -> Vec<u8> {
-    if method == METHOD_UNCOMPRESSED {
-        assert!(content.len() == decompressed_size, "Sizes must be the same when uncompressed!");
-        content.to_vec()
-    } else if method == METHOD_LZW {
-        lzw::decompress(content, decompressed_size)
-    } else if method == METHOD_HUFFMAN {
-        huffman::decompress(content, decompressed_size)
-    } else {
-        panic!("Unknown compression method: {}", method);
+    let compression = entry.generation.compression(header.method).expect("Header::parse checks the method");
+    let decompressed = decompress(&content, header.decompressed_size, compression)
+        .map_err(|e| format!("can't be decompressed: {}", e))?;
+    Ok((header, decompressed))
+}
+
+impl Header {
+    // Checks the sizes and method are plausible, so a corrupt header is caught before trying to decompress.
+    pub fn parse(data: &[u8], generation: Generation) -> Result<Header, String> {
+        let header = Header::parse_sizes(data, generation)?;
+        match generation.compression(header.method) {
+            None => return Err(format!("has an unknown compression method: {}", header.method)),
+            Some(Compression::Unsupported(name)) => return Err(format!("uses {} compression, which isn't supported yet", name)),
+            Some(Compression::None) if header.compressed_size != header.decompressed_size => {
+                return Err(format!("is uncompressed but has different sizes: {} and {}", header.compressed_size, header.decompressed_size))
+            }
+            _ => {}
+        }
+        Ok(header)
+    }
+
+    // Just the fields, for reading a resource as stored whatever its method.
+    // Late SCI1 headers start with a type byte, then the number, rather than the id.
+    pub fn parse_sizes(data: &[u8], generation: Generation) -> Result<Header, String> {
+        let (id, data) = if generation == Generation::Sci1Late {
+            (((data[0] as usize & 0x7f) << 11) | (data[1] as usize) | ((data[2] as usize) << 8), &data[1..])
+        } else {
+            ((data[0] as usize) + ((data[1] as usize) << 8), data)
+        };
+        let compressed_size  = (data[2] as usize) + ((data[3] as usize) << 8);
+        let decompressed_size = (data[4] as usize) + ((data[5] as usize) << 8);
+        let method = (data[6] as usize) + ((data[7] as usize) << 8);
+        // Compressed size actually starts counting at the record position of decompressed_size:
+        let compressed_size = compressed_size.checked_sub(4).ok_or(format!("has an impossible compressed size of {}", compressed_size))?;
+        Ok(Header { id, compressed_size, decompressed_size, method })
+    }
+}
+
+pub fn decompress(content: &[u8], decompressed_size: usize, compression: Compression) -> Result<Vec<u8>, String> {
+    match compression {
+        Compression::None => {
+            assert!(content.len() == decompressed_size, "Sizes must be the same when uncompressed!");
+            Ok(content.to_vec())
+        }
+        Compression::Lzw => lzw::decompress(content, decompressed_size),
+        Compression::Huffman => huffman::decompress(content, decompressed_size),
+        Compression::Unsupported(name) => panic!("Unsupported compression: {}", name),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::map::{Map, ResourceType};
+    use crate::resource_source::Memory;
+    use crate::resource_writer::{self, Resource};
+
+    pub const SAMPLE_PATH: &str = "data/NewYearsMystery";
+
+    pub fn sample_game() -> (Map, Files) {
+        (Map::read(SAMPLE_PATH), Files::open(SAMPLE_PATH))
+    }
+
+    // Every resource in the sample game, with its entry.
+    pub fn sample_resources() -> Vec<(Entry, Vec<u8>)> {
+        let (map, files) = sample_game();
+        map.entries.into_iter().map(|entry| {
+            let data = read(&entry, &files);
+            (entry, data)
+        }).collect()
+    }
+
+    pub fn assert_sample_resources_round_trip(compress: fn(&[u8]) -> Vec<u8>, decompress: fn(&[u8], usize) -> Result<Vec<u8>, String>) {
+        for (entry, resource) in sample_resources() {
+            let compressed = compress(&resource);
+            assert!(decompress(&compressed, resource.len()).unwrap() == resource, "{:?} {} did not round trip", entry.resource_type, entry.resource_number);
+        }
+    }
+
+    // A scratch folder, removed on drop so failed asserts don't leave it behind.
+    pub struct TempDir {
+        pub path: String,
+    }
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("sci-quest-decoder-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir { path: path.to_str().unwrap().to_string() }
+        }
+
+        pub fn join(&self, name: &str) -> String {
+            format!("{}/{}", self.path, name)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    // Two disks with a copy of the same view on each.
+    pub fn two_disks(first: Vec<u8>, second: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let resource = |data: Vec<u8>| Resource { type_number: ResourceType::View.number(), resource_number: 1, data, method: METHOD_LZW };
+        let packed = resource_writer::pack(&[resource(first), resource(second)], Some(1));
+        let mut files = vec![("resource.map".to_string(), packed.map)];
+        for (i, volume) in packed.volumes.into_iter().enumerate() {
+            files.push((format!("resource.{:03}", i + 1), volume));
+        }
+        files
+    }
+
+    #[test]
+    fn it_keeps_every_copy() {
+        let memory = Memory { files: two_disks(vec![1, 2, 3], vec![1, 2, 4]) };
+        let map = Map::read_from(&memory);
+        assert_eq!(map.entries.len(), 1);
+        assert_eq!(map.entries[0].other_copies, vec![Location { file: 2, offset: 0 }]);
+        let files = Files::new(Box::new(memory));
+        let copies = read_copies(&map.entries[0], &files);
+        assert_eq!(copies.iter().map(|(_, r)| r.clone().unwrap()).collect::<Vec<_>>(), vec![vec![1, 2, 3], vec![1, 2, 4]]);
+        assert_eq!(differing_copies(&copies), vec![(Location { file: 1, offset: 0 }, Location { file: 2, offset: 0 })]);
+        assert!(differing_copies(&copies[..1]).is_empty());
+    }
+
+    #[test]
+    fn it_falls_back_to_another_copy() {
+        let mut disks = two_disks(vec![5; 10], vec![5; 10]);
+        let map = Map::read_from(&Memory { files: disks.clone() });
+        disks[1].1.truncate(12); // Disk 1 is a bad dump.
+        let files = Files::new(Box::new(Memory { files: disks.clone() }));
+        assert_eq!(read(&map.entries[0], &files), vec![5; 10]);
+        assert_eq!(read_first_copy(&map.entries[0], &files).unwrap().0, Location { file: 2, offset: 0 });
+        disks.remove(1); // Disk 1 is missing.
+        let files = Files::new(Box::new(Memory { files: disks }));
+        assert_eq!(read(&map.entries[0], &files), vec![5; 10]);
     }
 }
@@ -0,0 +1,174 @@
+// This is responsible for where a game's files come from: a folder, a zip archive of one, or memory for tests.
+// File names are matched ignoring case, since DOS games tend to have upper case names, eg RESOURCE.MAP.
+
+use crate::zip;
+use std::io::{Read, Seek, SeekFrom};
+
+// Somewhere resource bytes can be read from by position, eg a file on disk.
+// Reads give None if they go past the end or fail, eg a bad floppy dump.
+pub trait Volume: Send {
+    fn read_at(&mut self, offset: usize, length: usize) -> Option<Vec<u8>>;
+    fn size(&mut self) -> usize;
+}
+
+impl Volume for std::fs::File {
+    fn read_at(&mut self, offset: usize, length: usize) -> Option<Vec<u8>> {
+        self.seek(SeekFrom::Start(offset as u64)).ok()?;
+        let mut buffer: Vec<u8> = vec![0; length];
+        self.read_exact(&mut buffer).ok()?;
+        Some(buffer)
+    }
+
+    fn size(&mut self) -> usize {
+        self.metadata().unwrap().len() as usize
+    }
+}
+
+impl Volume for Vec<u8> {
+    fn read_at(&mut self, offset: usize, length: usize) -> Option<Vec<u8>> {
+        self.get(offset..(offset + length)).map(|b| b.to_vec())
+    }
+
+    fn size(&mut self) -> usize {
+        self.len()
+    }
+}
+
+// Part of a file, eg a stored file in a zip, so it can still be read lazily.
+struct Window {
+    file: std::fs::File,
+    start: usize,
+    size: usize,
+}
+
+impl Volume for Window {
+    fn read_at(&mut self, offset: usize, length: usize) -> Option<Vec<u8>> {
+        if offset + length > self.size { return None }
+        self.file.read_at(self.start + offset, length)
+    }
+
+    fn size(&mut self) -> usize {
+        self.size
+    }
+}
+
+pub trait Source: Send + Sync {
+    fn names(&self) -> Vec<String>;
+    fn open(&self, name: &str) -> Option<Box<dyn Volume>>;
+}
+
+// Picks a source for the path given on the command line.
+pub fn open(path: &str) -> Box<dyn Source> {
+    if path.to_lowercase().ends_with(".zip") {
+        Box::new(Zip::open(path))
+    } else {
+        Box::new(Directory { path: path.to_string() })
+    }
+}
+
+pub struct Directory {
+    pub path: String,
+}
+
+impl Source for Directory {
+    fn names(&self) -> Vec<String> {
+        let Ok(dir) = std::fs::read_dir(&self.path) else { return Vec::new() };
+        dir.filter_map(|e| e.ok()?.file_name().into_string().ok()).collect()
+    }
+
+    fn open(&self, name: &str) -> Option<Box<dyn Volume>> {
+        let actual_name = self.names().into_iter().find(|n| n.eq_ignore_ascii_case(name))?;
+        let file = std::fs::File::open(format!("{}/{}", self.path, actual_name)).ok()?;
+        Some(Box::new(file))
+    }
+}
+
+// Files are found by name regardless of which folder they're in within the archive, first one wins.
+pub struct Zip {
+    path: String,
+    entries: Vec<zip::Entry>,
+}
+
+impl Zip {
+    pub fn open(path: &str) -> Zip {
+        let mut file = std::fs::File::open(path).unwrap_or_else(|e| panic!("Couldn't open {}: {}", path, e));
+        let entries = zip::entries(&mut file).into_iter().filter(|e| !e.name.ends_with('/')).collect();
+        Zip { path: path.to_string(), entries }
+    }
+}
+
+impl Source for Zip {
+    fn names(&self) -> Vec<String> {
+        self.entries.iter().map(|e| base_name(&e.name).to_string()).collect()
+    }
+
+    fn open(&self, name: &str) -> Option<Box<dyn Volume>> {
+        let entry = self.entries.iter().find(|e| base_name(&e.name).eq_ignore_ascii_case(name))?;
+        let mut file = std::fs::File::open(&self.path).unwrap();
+        // Corrupt entries can't be read, like a missing file.
+        if entry.method == zip::METHOD_STORED {
+            let start = zip::data_offset(&mut file, entry).ok()?;
+            Some(Box::new(Window { file, start, size: entry.size }))
+        } else {
+            Some(Box::new(zip::read(&mut file, entry).ok()?)) // Deflated files can't be read from the middle.
+        }
+    }
+}
+
+fn base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+pub struct Memory {
+    pub files: Vec<(String, Vec<u8>)>, // Name, content.
+}
+
+#[cfg(test)]
+impl Source for Memory {
+    fn names(&self) -> Vec<String> {
+        self.files.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn open(&self, name: &str) -> Option<Box<dyn Volume>> {
+        let (_, content) = self.files.iter().find(|(n, _)| n.eq_ignore_ascii_case(name))?;
+        Some(Box::new(content.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::resource_files::Files;
+    use crate::resource_reader::{self, tests::{sample_resources, TempDir, SAMPLE_PATH}};
+
+    #[test]
+    fn it_reads_games_from_memory() {
+        let memory = Memory { files: vec![
+            ("RESOURCE.MAP".to_string(), std::fs::read(format!("{}/resource.map", SAMPLE_PATH)).unwrap()),
+            ("RESOURCE.001".to_string(), std::fs::read(format!("{}/resource.001", SAMPLE_PATH)).unwrap()),
+        ] };
+        let map = map::Map::read_from(&memory);
+        let files = Files::new(Box::new(memory));
+        let directory_resources = sample_resources();
+        assert_eq!(map.entries.len(), directory_resources.len());
+        for (entry, (_, data)) in map.entries.iter().zip(&directory_resources) {
+            assert!(&resource_reader::read(entry, &files) == data);
+        }
+    }
+
+    #[test]
+    fn it_treats_corrupt_zip_entries_as_missing() {
+        let archive = crate::zip::tests::zip_data(&[
+            ("RESOURCE.MAP", zip::METHOD_STORED, b"map", 3),
+            ("RESOURCE.001", zip::METHOD_DEFLATED, &[0x4b, 0x4c, 0x4a], 9), // Cut short.
+        ]);
+        let dir = TempDir::new("corrupt");
+        let path = dir.join("game.zip");
+        std::fs::write(&path, archive).unwrap();
+        let zip = Zip::open(&path);
+        assert!(zip.open("resource.map").is_some());
+        assert!(zip.open("resource.001").is_none());
+    }
+}
@@ -0,0 +1,131 @@
+// This is responsible for writing resources back out as a fresh resource.map and resource.00x volumes,
+// the inverse of resource_reader, resource_files and map.
+// https://wiki.scummvm.org/index.php?title=SCI/Specifications/Resource_files/SCI0_resources
+
+use crate::huffman;
+use crate::lzw;
+use crate::map::ResourceType;
+use crate::resource_reader::{HEADER_SIZE, METHOD_HUFFMAN, METHOD_LZW, METHOD_UNCOMPRESSED};
+
+const MAX_OFFSET: usize = 0x3ffffff; // Offsets in the map are 26 bits.
+const MAX_FILE: usize = 0b111111; // Leaving 6 bits for the volume number.
+const MAX_RESOURCE_NUMBER: usize = 0b111_11111111;
+
+pub struct Resource {
+    pub type_number: usize, // Rather than the ResourceType, so unknown types keep their number.
+    pub resource_number: usize,
+    pub data: Vec<u8>, // Decompressed.
+    pub method: usize, // How to compress it in the volume.
+}
+
+// The contents of the files to write, volumes[0] being resource.001.
+pub struct Packed {
+    pub map: Vec<u8>,
+    pub volumes: Vec<Vec<u8>>,
+}
+
+// Compresses each resource and appends it to the current volume, starting a new volume when the next one
+// wouldn't fit under max_volume_size, eg to fit floppies.
+pub fn pack(resources: &[Resource], max_volume_size: Option<usize>) -> Packed {
+    let max_volume_size = max_volume_size.unwrap_or(MAX_OFFSET).min(MAX_OFFSET);
+    let mut map: Vec<u8> = Vec::new();
+    let mut volumes: Vec<Vec<u8>> = vec![Vec::new()];
+    for resource in resources {
+        assert!(resource.resource_number <= MAX_RESOURCE_NUMBER, "Resource number {} is too big for the map!", resource.resource_number);
+        let id = (resource.type_number << 11) | resource.resource_number;
+        let content = compress(&resource.data, resource.method);
+        assert!(resource.data.len() <= 0xffff && content.len() + 4 <= 0xffff, "{:?} {} is too big for SCI0!", ResourceType::from(resource.type_number), resource.resource_number);
+
+        let record_size = HEADER_SIZE + content.len();
+        let volume = volumes.last().unwrap();
+        if !volume.is_empty() && volume.len() + record_size > max_volume_size {
+            volumes.push(Vec::new());
+        }
+        let file = volumes.len();
+        assert!(file <= MAX_FILE, "Too many volumes for the map, try a bigger volume size!");
+        let volume = volumes.last_mut().unwrap();
+        let offset = volume.len();
+        assert!(offset <= MAX_OFFSET, "Volume is too big for the map's offsets!");
+
+        append_u16(volume, id);
+        append_u16(volume, content.len() + 4); // Compressed size counts from the decompressed size field.
+        append_u16(volume, resource.data.len());
+        append_u16(volume, resource.method);
+        volume.extend(content);
+
+        append_u16(&mut map, id);
+        let rest = (file << 26) | offset;
+        append_u16(&mut map, rest & 0xffff);
+        append_u16(&mut map, rest >> 16);
+    }
+    map.extend([0xff; 6]); // Terminator.
+    Packed { map, volumes }
+}
+
+// Writes resource.map and resource.00x into the given folder.
+pub fn write(path: &str, packed: &Packed) {
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(format!("{}/resource.map", path), &packed.map).unwrap();
+    for (i, volume) in packed.volumes.iter().enumerate() {
+        std::fs::write(format!("{}/resource.{:03}", path, i + 1), volume).unwrap();
+    }
+}
+
+fn compress(data: &[u8], method: usize) -> Vec<u8> {
+    if method == METHOD_UNCOMPRESSED {
+        data.to_vec()
+    } else if method == METHOD_LZW {
+        lzw::compress(data)
+    } else if method == METHOD_HUFFMAN {
+        huffman::compress(data)
+    } else {
+        panic!("Unknown compression method: {}", method);
+    }
+}
+
+fn append_u16(out: &mut Vec<u8>, value: usize) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::resource_files;
+    use crate::resource_reader::{self, tests::{sample_resources, TempDir}};
+
+    #[test]
+    fn it_repacks_sample_resources() {
+        let methods = [METHOD_UNCOMPRESSED, METHOD_LZW, METHOD_HUFFMAN];
+        let resources: Vec<Resource> = sample_resources().into_iter().enumerate().map(|(i, (e, data))| Resource {
+            type_number: e.id >> 11,
+            resource_number: e.resource_number,
+            data,
+            method: methods[i % methods.len()],
+        }).collect();
+        let packed = pack(&resources, Some(50_000));
+        assert!(packed.volumes.len() > 1, "Expected the volume size to split the resources!");
+        assert!(packed.volumes.iter().all(|v| v.len() <= 50_000));
+
+        let out = TempDir::new("repack");
+        let out_path = out.path.as_str();
+        write(out_path, &packed);
+        let repacked_map = map::Map::read(out_path);
+        let repacked_files = resource_files::Files::open(out_path);
+        assert_eq!(repacked_map.entries.len(), resources.len());
+        for (entry, resource) in repacked_map.entries.iter().zip(&resources) {
+            assert_eq!((entry.id >> 11, entry.resource_number), (resource.type_number, resource.resource_number));
+            assert!(resource_reader::read(entry, &repacked_files) == resource.data);
+        }
+    }
+
+    #[test]
+    fn it_keeps_unknown_types() {
+        let packed = pack(&[Resource { type_number: 25, resource_number: 7, data: vec![1, 2, 3], method: METHOD_UNCOMPRESSED }], None);
+        let memory = crate::resource_source::Memory { files: vec![("resource.map".to_string(), packed.map), ("resource.001".to_string(), packed.volumes[0].clone())] };
+        let map = map::Map::read_from(&memory);
+        assert_eq!((map.entries[0].resource_type, map.entries[0].id >> 11, map.entries[0].resource_number), (ResourceType::Unknown, 25, 7));
+        assert_eq!(resource_reader::read(&map.entries[0], &resource_files::Files::new(Box::new(memory))), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,189 @@
+// This is responsible for packing every cel of a view into one sprite sheet, with a JSON descriptor,
+// for importing into engines that want atlases rather than loose files.
+// Each loop gets its own row, with its cels left to right, so the sheet is easy to eyeball too.
+// Cels keep their original size and EGA palette indexes, with a pixel of transparent padding between them
+// so filtering in an engine doesn't bleed neighbouring cels.
+// Sheets can be read back in after editing, to build a replacement view.
+
+use crate::json;
+use crate::palette::{PALETTE, TRANSPARENT};
+use crate::view::{Cel, Loop, View};
+
+const PADDING: usize = 1;
+
+pub struct SpriteSheet {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>, // EGA palette indexes.
+    pub loops: Vec<LoopPlacement>,
+}
+
+pub struct LoopPlacement {
+    pub is_mirrored: bool,
+    pub cels: Vec<CelPlacement>,
+}
+
+pub struct CelPlacement {
+    pub x: usize, // Rectangle within the sheet.
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub x_placement: i16, // Offset the game draws it at, relative to the actor's position. Mirrored loops draw at minus the stored one.
+    pub y_placement: i8,
+}
+
+pub fn pack(view: &View) -> SpriteSheet {
+    // Work out where everything goes first.
+    let mut loops: Vec<LoopPlacement> = Vec::with_capacity(view.loops.len());
+    let mut width = 0;
+    let mut y = 0;
+    for l in &view.loops {
+        let mut x = 0;
+        let mut cels: Vec<CelPlacement> = Vec::with_capacity(l.cels.len());
+        for c in &l.cels {
+            let x_placement = if l.is_mirrored { -(c.x_placement as i16) } else { c.x_placement as i16 };
+            cels.push(CelPlacement { x, y, width: c.width, height: c.height, x_placement, y_placement: c.y_placement });
+            x += c.width + PADDING;
+        }
+        width = width.max(x.saturating_sub(PADDING));
+        y += l.cels.iter().map(|c| c.height).max().unwrap_or(0) + PADDING;
+        loops.push(LoopPlacement { is_mirrored: l.is_mirrored, cels });
+    }
+    let width = width.max(1);
+    let height = y.saturating_sub(PADDING).max(1);
+
+    // Then copy the cels in.
+    let mut pixels: Vec<u8> = vec![TRANSPARENT; width * height];
+    for (l, placement) in view.loops.iter().zip(&loops) {
+        for (c, rect) in l.cels.iter().zip(&placement.cels) {
+            if c.width == 0 || c.height == 0 { continue } // Empty cels still get a placement, but have nothing to copy.
+            for (row_index, row) in c.pixels[..(c.width * c.height)].chunks_exact(c.width).enumerate() {
+                let start = (rect.y + row_index) * width + rect.x;
+                pixels[start..(start + c.width)].copy_from_slice(row);
+            }
+        }
+    }
+    SpriteSheet { width, height, pixels, loops }
+}
+
+impl SpriteSheet {
+    // Hand-written to avoid a dependency, it's simple enough.
+    pub fn json(&self, image_name: &str) -> String {
+        let mut json = String::new();
+        json.push_str("{\n");
+        json.push_str(&format!("  \"image\": {},\n", json::quote(image_name)));
+        json.push_str(&format!("  \"width\": {},\n", self.width));
+        json.push_str(&format!("  \"height\": {},\n", self.height));
+        json.push_str("  \"loops\": [\n");
+        for (li, l) in self.loops.iter().enumerate() {
+            json.push_str(&format!("    {{\n      \"loop\": {},\n      \"mirrored\": {},\n      \"cels\": [\n", li, l.is_mirrored));
+            for (ci, c) in l.cels.iter().enumerate() {
+                json.push_str(&format!(
+                    "        {{ \"cel\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"x_placement\": {}, \"y_placement\": {} }}{}\n",
+                    ci, c.x, c.y, c.width, c.height, c.x_placement, c.y_placement,
+                    if ci + 1 < l.cels.len() { "," } else { "" }));
+            }
+            json.push_str(&format!("      ]\n    }}{}\n", if li + 1 < self.loops.len() { "," } else { "" }));
+        }
+        json.push_str("  ]\n}\n");
+        json
+    }
+}
+
+// Cuts the cels back out of an (edited) sheet, using its descriptor.
+// The sheet's colours must be exact EGA colours, or fully transparent.
+pub fn unpack(descriptor: &str, sheet_width: usize, sheet_rgbas: &[u32]) -> View {
+    let descriptor = json::parse(descriptor).unwrap_or_else(|e| panic!("Sprite sheet descriptor: {}", e));
+    let number = |value: &json::Value, key: &str| -> f64 {
+        value.get(key).and_then(|v| v.as_f64()).unwrap_or_else(|| panic!("Sprite sheet descriptor is missing a number for: {}", key))
+    };
+    let pixels = indexes_from_rgbas(sheet_width, sheet_rgbas);
+    let sheet_height = pixels.len() / sheet_width;
+    let loops_json = descriptor.get("loops").and_then(|l| l.as_array()).expect("Sprite sheet descriptor is missing loops!");
+    let loops: Vec<Loop> = loops_json.iter().map(|l| {
+        let is_mirrored = l.get("mirrored").and_then(|m| m.as_bool()).unwrap_or(false);
+        let cels_json = l.get("cels").and_then(|c| c.as_array()).expect("Sprite sheet descriptor loop is missing cels!");
+        let cels: Vec<Cel> = cels_json.iter().map(|c| {
+            let (x, y) = (number(c, "x") as usize, number(c, "y") as usize);
+            let (width, height) = (number(c, "width") as usize, number(c, "height") as usize);
+            assert!(x + width <= sheet_width && y + height <= sheet_height, "Cel at {},{} goes outside the sprite sheet!", x, y);
+            let mut cel_pixels: Vec<u8> = Vec::with_capacity(width * height);
+            for row in y..(y + height) {
+                cel_pixels.extend_from_slice(&pixels[(row * sheet_width + x)..(row * sheet_width + x + width)]);
+            }
+            let x_placement = c.get("x_placement").and_then(|v| v.as_f64()).unwrap_or(0.0);
+            let x_placement = if is_mirrored { -x_placement } else { x_placement } as i8;
+            let y_placement = c.get("y_placement").and_then(|v| v.as_f64()).unwrap_or(0.0) as i8;
+            Cel { width, height, pixels: cel_pixels, x_placement, y_placement }
+        }).collect();
+        Loop { cels, is_mirrored }
+    }).collect();
+    View { loops }
+}
+
+fn indexes_from_rgbas(width: usize, rgbas: &[u32]) -> Vec<u8> {
+    rgbas.iter().enumerate().map(|(i, &rgba)| {
+        if rgba & 0xff == 0 { return TRANSPARENT }
+        let index = PALETTE[..16].iter().position(|&p| p == rgba);
+        index.unwrap_or_else(|| panic!("Sprite sheet pixel {},{} is #{:08x}, which isn't an EGA colour!", i % width, i / width, rgba)) as u8
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_packs_loops_into_rows() {
+        let cel = |width: usize, height: usize, colour: u8| Cel { width, height, pixels: vec![colour; width * height], x_placement: -1, y_placement: 2 };
+        let view = View { loops: vec![
+            Loop { cels: vec![cel(2, 2, 1), cel(1, 3, 2)], is_mirrored: false },
+            Loop { cels: vec![cel(3, 1, 3)], is_mirrored: true },
+        ] };
+        let sheet = pack(&view);
+        assert_eq!((sheet.width, sheet.height), (4, 5));
+        let expected: Vec<u8> = vec![
+            1, 1, 16, 2,
+            1, 1, 16, 2,
+            16, 16, 16, 2,
+            16, 16, 16, 16,
+            3, 3, 3, 16,
+        ];
+        assert_eq!(sheet.pixels, expected);
+        assert_eq!((sheet.loops[0].cels[1].x, sheet.loops[1].cels[0].y), (3, 4));
+        let json = sheet.json("sheet \"1\".png");
+        assert!(json.contains("\"image\": \"sheet \\\"1\\\".png\""));
+        assert!(json.contains("\"mirrored\": true"));
+        assert_eq!((sheet.loops[0].cels[0].x_placement, sheet.loops[1].cels[0].x_placement), (-1, 1)); // As drawn.
+    }
+
+    #[test]
+    fn it_packs_empty_cels() {
+        let view = View { loops: vec![
+            Loop { cels: vec![
+                Cel { width: 0, height: 2, pixels: vec![], x_placement: 0, y_placement: 0 },
+                Cel { width: 2, height: 0, pixels: vec![TRANSPARENT], x_placement: 0, y_placement: 0 }, // Parsing can leave a pixel over.
+                Cel { width: 1, height: 1, pixels: vec![4], x_placement: 0, y_placement: 0 },
+            ], is_mirrored: false },
+        ] };
+        let sheet = pack(&view);
+        assert_eq!((sheet.width, sheet.height), (5, 2));
+        assert_eq!(sheet.pixels, [16, 16, 16, 16, 4, 16, 16, 16, 16, 16]);
+        assert_eq!(sheet.loops[0].cels[2].x, 4);
+    }
+
+    #[test]
+    fn it_unpacks_what_it_packs() {
+        let view = View { loops: vec![
+            Loop { cels: vec![Cel { width: 2, height: 1, pixels: vec![1, TRANSPARENT], x_placement: 3, y_placement: -4 }], is_mirrored: true },
+            Loop { cels: vec![Cel { width: 1, height: 1, pixels: vec![2], x_placement: -128, y_placement: 0 }], is_mirrored: true },
+        ] };
+        let sheet = pack(&view);
+        let rgbas: Vec<u32> = sheet.pixels.iter().map(|&p| PALETTE[p as usize]).collect();
+        let unpacked = unpack(&sheet.json("sheet.png"), sheet.width, &rgbas);
+        let cel = &unpacked.loops[0].cels[0];
+        assert!(unpacked.loops[0].is_mirrored);
+        assert_eq!((cel.pixels.clone(), cel.x_placement, cel.y_placement), (vec![1, TRANSPARENT], 3, -4));
+        assert_eq!(unpacked.loops[1].cels[0].x_placement, -128);
+    }
+}
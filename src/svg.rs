@@ -0,0 +1,194 @@
+// This is responsible for exporting pictures as SVG vector drawings, so they can be printed at any size.
+// Lines become polylines, solid patterns become circles/rectangles and textured patterns become their pixels.
+// Flood fills can't be expressed as vectors directly, so the raster is drawn alongside and
+// each fill's changed pixels are traced into rectangles.
+// Dithered colours are averaged, as the checkerboard would just look like noise at poster size.
+// https://developer.mozilla.org/en-US/docs/Web/SVG
+
+use crate::picture::{self, Painter, Picture, WIDTH, HEIGHT};
+use crate::picture_disassembler::{self, Point};
+use crate::picture_splitter::{self, Action};
+use crate::renderer;
+use std::ops::Range;
+
+// Pixels were 1.2x higher than wide on the original 4:3 screen, so the same 5w x 6h ratio as the PNGs is used.
+// This also gives it the same default size as the PNGs.
+const WIDTH_MULTIPLIER: usize = 5;
+const HEIGHT_MULTIPLIER: usize = 6;
+
+pub fn svg_from_picture_resource(data: &[u8]) -> Result<String, String> {
+    let actions = picture_splitter::split(data)?;
+    let mut canvas = Picture::blank();
+    let mut painter = Painter::on(&mut canvas);
+    let mut scratch: Vec<u8> = vec![0; WIDTH * HEIGHT];
+    let mut elements: Vec<String> = Vec::new();
+    for a in &actions {
+        let before = if a.action == Action::FloodFill { Some(painter.canvas.picture.clone()) } else { None };
+        painter.apply(a);
+        if !painter.is_drawing { continue }
+        let texture_bytes = if painter.is_pattern { 1 } else { 0 };
+        let colour = colour_from_dither_double(painter.colour);
+        match a.action {
+            Action::LongLines => elements.push(polyline(&picture_disassembler::decode_long(&a.arguments, 0), &colour)),
+            Action::MediumRelativeLines => elements.push(polyline(&picture_disassembler::decode_medium(&a.arguments, 0), &colour)),
+            Action::ShortRelativeLines => elements.push(polyline(&picture_disassembler::decode_short(&a.arguments, 0), &colour)),
+            Action::LongPatterns => {
+                elements.extend(patterns(&picture_disassembler::decode_long(&a.arguments, texture_bytes), &painter, &mut scratch, &colour));
+            }
+            Action::MediumRelativePatterns => {
+                elements.extend(patterns(&picture_disassembler::decode_medium(&a.arguments, texture_bytes), &painter, &mut scratch, &colour));
+            }
+            Action::ShortRelativePatterns => {
+                elements.extend(patterns(&picture_disassembler::decode_short(&a.arguments, texture_bytes), &painter, &mut scratch, &colour));
+            }
+            Action::FloodFill => {
+                let Some(before) = before else { continue };
+                let after = &painter.canvas.picture;
+                let path = path_from_pixels(|i| before[i] != after[i], 0..WIDTH, 0..HEIGHT);
+                if !path.is_empty() {
+                    elements.push(format!("<path d=\"{}\" fill=\"{}\" shape-rendering=\"crispEdges\"/>", path, colour));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let aspect_height = HEIGHT * HEIGHT_MULTIPLIER / WIDTH_MULTIPLIER;
+    let mut svg = String::new();
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        WIDTH * WIDTH_MULTIPLIER, HEIGHT * HEIGHT_MULTIPLIER, WIDTH, aspect_height));
+    svg.push_str(&format!("<g transform=\"scale(1 {})\">\n", (HEIGHT_MULTIPLIER as f32) / (WIDTH_MULTIPLIER as f32)));
+    svg.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", WIDTH, HEIGHT, colour_from_dither_double(painter.background)));
+    for e in elements {
+        svg.push_str(&e);
+        svg.push('\n');
+    }
+    svg.push_str("</g>\n</svg>\n");
+    Ok(svg)
+}
+
+fn colour_from_dither_double(dither_double: u8) -> String {
+    let rgba = renderer::rgba_from_indexed_colour(dither_double, true);
+    format!("#{:06x}", rgba >> 8)
+}
+
+// Lines go through the centre of each pixel.
+fn polyline(points: &[Point], colour: &str) -> String {
+    let coordinates: Vec<String> = points.iter().map(|p| format!("{}.5,{}.5", p.x, p.y)).collect();
+    format!("<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1\" stroke-linecap=\"square\" stroke-linejoin=\"round\"/>",
+        coordinates.join(" "), colour)
+}
+
+fn patterns(points: &[Point], painter: &Painter, scratch: &mut [u8], colour: &str) -> Vec<String> {
+    let size = painter.pattern_size as isize;
+    points.iter().filter(|p| p.x >= 0 && p.y >= 0).filter_map(|p| {
+        if painter.is_pattern {
+            // Textured patterns are a spray of pixels, so draw them on the scratch canvas to find which.
+            picture::draw_pattern(scratch, 1, p.x as usize, p.y as usize, p.texture.unwrap_or(0) as usize, painter.pattern_size as usize, true, painter.is_rectangle);
+            let (xs, ys) = pattern_area(p.x, p.y, size);
+            let path = path_from_pixels(|i| scratch[i] != 0, xs.clone(), ys.clone());
+            for y in ys {
+                scratch[(y * WIDTH + xs.start)..(y * WIDTH + xs.end)].fill(0);
+            }
+            if path.is_empty() { return None } // Entirely off screen.
+            Some(format!("<path d=\"{}\" fill=\"{}\" shape-rendering=\"crispEdges\"/>", path, colour))
+        } else if painter.is_rectangle {
+            Some(format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>", p.x - size, p.y - size, size * 2 + 1, size * 2 + 1, colour))
+        } else {
+            Some(format!("<circle cx=\"{}.5\" cy=\"{}.5\" r=\"{}.5\" fill=\"{}\"/>", p.x, p.y, size, colour))
+        }
+    }).collect()
+}
+
+// The columns and rows a pattern can touch, within the screen. Empty if it's entirely off screen.
+fn pattern_area(x: isize, y: isize, size: isize) -> (Range<usize>, Range<usize>) {
+    let clamp = |value: isize, length: usize| value.clamp(0, length as isize) as usize;
+    let xs = clamp(x - size, WIDTH)..clamp(x + size + 1, WIDTH);
+    let ys = clamp(y - size, HEIGHT)..clamp(y + size + 1, HEIGHT);
+    (xs, ys)
+}
+
+// Traces set pixels within an area of the screen into a path of rectangles.
+// Each row is split into runs, and a run continues the rectangle above if it spans the same columns.
+fn path_from_pixels(is_set: impl Fn(usize) -> bool, xs: Range<usize>, ys: Range<usize>) -> String {
+    let mut path = String::new();
+    let mut open: Vec<(usize, usize, usize)> = Vec::new(); // Start x, end x, start y.
+    for y in ys.start..=ys.end {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        if y < ys.end {
+            let row = y * WIDTH;
+            let mut x = xs.start;
+            while x < xs.end {
+                if !is_set(row + x) { x += 1; continue }
+                let start = x;
+                while x < xs.end && is_set(row + x) { x += 1; }
+                runs.push((start, x));
+            }
+        }
+        let mut still_open: Vec<(usize, usize, usize)> = Vec::new();
+        for &(start, end, start_y) in &open {
+            if runs.contains(&(start, end)) {
+                still_open.push((start, end, start_y));
+            } else {
+                path.push_str(&format!("M{} {}h{}v{}h-{}z", start, start_y, end - start, y - start_y, end - start));
+            }
+        }
+        for &(start, end) in &runs {
+            if !still_open.iter().any(|o| o.0 == start && o.1 == end) {
+                still_open.push((start, end, y));
+            }
+        }
+        open = still_open;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::resource_reader::tests::sample_resources;
+
+    #[test]
+    fn it_draws_a_sample_picture_as_elements() {
+        let (_, resource) = sample_resources().into_iter().find(|(e, _)| e.resource_type == map::ResourceType::Picture && e.resource_number == 1).unwrap();
+        let svg = svg_from_picture_resource(&resource).unwrap();
+
+        let lines: Vec<&str> = svg.lines().collect();
+        assert!(lines[0].starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1600\" height=\"1140\""));
+        assert_eq!(lines[1], "<g transform=\"scale(1 1.2)\">");
+        assert_eq!(lines[2], "<rect width=\"320\" height=\"190\" fill=\"#ffffff\"/>");
+        assert_eq!(&lines[(lines.len() - 2)..], ["</g>", "</svg>"]);
+        let elements = &lines[3..(lines.len() - 2)];
+        assert!(elements.iter().all(|e| ["<polyline ", "<path ", "<circle ", "<rect "].iter().any(|t| e.starts_with(t)) && e.ends_with("/>")));
+
+        // Each line action that draws becomes one polyline.
+        let mut canvas = Picture::blank();
+        let mut painter = Painter::on(&mut canvas);
+        let mut drawn_lines = 0;
+        for a in picture_splitter::split(&resource).unwrap() {
+            painter.apply(&a);
+            let is_line = [Action::LongLines, Action::MediumRelativeLines, Action::ShortRelativeLines].contains(&a.action);
+            if is_line && painter.is_drawing { drawn_lines += 1 }
+        }
+        assert_eq!(elements.iter().filter(|e| e.starts_with("<polyline ")).count(), drawn_lines);
+    }
+
+    #[test]
+    fn it_skips_patterns_off_the_screen() {
+        let resource = [0xf0, 0, 0xf9, 0x20, 0xfa, 0x00, 0x10, 0x90, 0x0a, 0xff]; // A textured pattern at 400,10.
+        let svg = svg_from_picture_resource(&resource).unwrap();
+        assert!(!svg.contains("<path "));
+        assert_eq!(pattern_area(400, 10, 0), (320..320, 10..11));
+    }
+
+    #[test]
+    fn it_traces_pixels_within_an_area() {
+        let mut pixels = vec![false; WIDTH * HEIGHT];
+        for (x, y) in [(10, 20), (11, 20), (10, 21), (11, 21), (12, 22), (40, 20)] {
+            pixels[y * WIDTH + x] = true;
+        }
+        assert_eq!(path_from_pixels(|i| pixels[i], 9..13, 19..23), "M10 20h2v2h-2zM12 22h1v1h-1z");
+    }
+}
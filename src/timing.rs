@@ -0,0 +1,116 @@
+// This is responsible for how fast view loops animate when exported.
+// SCI counts time in ticks of 1/60th of a second, and each actor has a 'cycle speed' of how many ticks
+// each cel is shown for, so walk cycles are described the same way here.
+// Speeds can be given on the command line, or per view in a 'cycle-speeds.txt' table in the game folder:
+//   # View number, then ticks per cel (comma separated to vary per cel).
+//   * 6
+//   222 4,4,8
+// https://sciwiki.sierrahelp.com/index.php/SCI_Companion_Help:Actor_class
+
+use crate::resource_source::{self, Source};
+
+pub const TICKS_PER_SECOND: u16 = 60;
+pub const TABLE_NAME: &str = "cycle-speeds.txt";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+    pub ticks_per_cel: Vec<u16>, // Repeated if a loop has more cels than this.
+    pub loop_count: u32, // 0 means loop forever.
+    pub is_explicit: bool, // The ticks were given on the command line, rather than being the default.
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing { ticks_per_cel: vec![12], loop_count: 0, is_explicit: false } // 1/5th of a second.
+    }
+}
+
+impl Timing {
+    pub fn ticks_for_cel(&self, cel_index: usize) -> u16 {
+        self.ticks_per_cel[cel_index % self.ticks_per_cel.len()]
+    }
+}
+
+// Parses '6' or '4,4,8'.
+pub fn parse_ticks(text: &str) -> Option<Vec<u16>> {
+    let ticks: Option<Vec<u16>> = text.split(',').map(|t| t.trim().parse().ok().filter(|&t| t > 0)).collect();
+    ticks.filter(|t| !t.is_empty())
+}
+
+// Per-view overrides, with '*' applying to views that aren't listed.
+pub struct Table {
+    default: Option<Vec<u16>>,
+    views: Vec<(usize, Vec<u16>)>,
+}
+
+impl Table {
+    pub fn read(path: &str) -> Table {
+        Table::read_from(resource_source::open(path).as_ref())
+    }
+
+    // A missing table is fine, as most games won't have one. Zipped games can have one too.
+    pub fn read_from(source: &dyn Source) -> Table {
+        let data = source.open(TABLE_NAME).and_then(|mut file| {
+            let size = file.size();
+            file.read_at(0, size)
+        });
+        match data {
+            Some(data) => Table::parse(&String::from_utf8_lossy(&data)),
+            None => Table { default: None, views: Vec::new() },
+        }
+    }
+
+    pub fn parse(text: &str) -> Table {
+        let mut table = Table { default: None, views: Vec::new() };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() { continue }
+            let (view, ticks) = line.split_once(char::is_whitespace).unwrap_or_else(|| panic!("{} line {}: Expected a view number and ticks!", TABLE_NAME, i + 1));
+            let ticks = parse_ticks(ticks).unwrap_or_else(|| panic!("{} line {}: Bad ticks: {}", TABLE_NAME, i + 1, ticks));
+            if view == "*" {
+                table.default = Some(ticks);
+            } else {
+                let view: usize = view.parse().unwrap_or_else(|_| panic!("{} line {}: Bad view number: {}", TABLE_NAME, i + 1, view));
+                table.views.push((view, ticks));
+            }
+        }
+        table
+    }
+
+    // Listed views win over the command line, as they're more specific, but the command line wins over '*'.
+    pub fn timing_for_view(&self, view_number: usize, base: &Timing) -> Timing {
+        let listed = self.views.iter().find(|(v, _)| *v == view_number).map(|(_, t)| t);
+        let ticks = if base.is_explicit { listed } else { listed.or(self.default.as_ref()) };
+        match ticks {
+            Some(ticks) => Timing { ticks_per_cel: ticks.clone(), ..base.clone() },
+            None => base.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_looks_up_views() {
+        let table = Table::parse("# Comment.\n* 6\n222 4,4,8 # Walking.\n");
+        let base = Timing { ticks_per_cel: vec![12], loop_count: 3, is_explicit: false };
+        assert_eq!(table.timing_for_view(222, &base), Timing { ticks_per_cel: vec![4, 4, 8], loop_count: 3, is_explicit: false });
+        assert_eq!(table.timing_for_view(1, &base).ticks_per_cel, vec![6]);
+        assert_eq!(table.timing_for_view(222, &base).ticks_for_cel(4), 4);
+        // Ticks from the command line replace '*', but not listed views.
+        let explicit = Timing { ticks_per_cel: vec![3], loop_count: 3, is_explicit: true };
+        assert_eq!(table.timing_for_view(1, &explicit), explicit);
+        assert_eq!(table.timing_for_view(222, &explicit).ticks_per_cel, vec![4, 4, 8]);
+    }
+
+    #[test]
+    fn it_reads_the_table_from_the_game_source() {
+        let base = Timing::default();
+        let memory = resource_source::Memory { files: vec![("CYCLE-SPEEDS.TXT".to_string(), b"* 6\n".to_vec())] };
+        assert_eq!(Table::read_from(&memory).timing_for_view(1, &base).ticks_per_cel, vec![6]);
+        let memory = resource_source::Memory { files: Vec::new() };
+        assert_eq!(Table::read_from(&memory).timing_for_view(1, &base), base);
+    }
+}
@@ -0,0 +1,327 @@
+// This is responsible for working out which interpreter generation a game was made for, and which game it is.
+// Generations differ in their map layout, volume headers and compression method numbers, so the decoders need to know.
+// The checks follow ScummVM's detection, plus a table of known games by the hash of their map.
+// https://github.com/scummvm/scummvm/blob/master/engines/sci/resource/resource.cpp
+// https://sciwiki.sierrahelp.com/index.php/Sierra_SCI_Release_List
+
+use crate::map::{self, Entry, Map, ResourceType};
+use crate::resource_files::Files;
+use crate::resource_reader::{self, Header};
+use crate::resource_source::{Source, Volume};
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Generation {
+    Sci0Early, // Older script header, eg King's Quest 4's first release.
+    Sci0Late,
+    Sci01, // SCI0 layout, but method 2 means COMP3 rather than Huffman.
+    Sci1Early, // SCI0 layout, but method 1 means Huffman and 2-4 mean LZW1, as in the rest of SCI1.
+    Sci1Late, // Map is indexed by type, and volume headers have a type byte.
+}
+
+pub enum Compression {
+    None,
+    Lzw,
+    Huffman,
+    Unsupported(&'static str),
+}
+
+pub struct Version {
+    pub title: String,
+    pub generation: Generation,
+    pub platform: &'static str,
+    pub reasons: Vec<String>, // How it was worked out.
+}
+
+// Hashes of resource.map, for the title and platform, which the resources themselves don't say.
+const KNOWN_GAMES: [(u64, &str, &str); 1] = [
+    (0x7dae274c65d89458, "New Year's Mystery", "DOS"),
+];
+
+// Reads a length of bytes at an offset in a named file, from wherever the game is being read.
+type Reader<'a> = dyn FnMut(&str, usize, usize) -> Option<Vec<u8>> + 'a;
+
+// What tells the SCI0 layout generations apart, following the order of ScummVM's checks.
+struct Evidence {
+    has_old_script_header: bool,
+    has_kernel_names: bool, // vocab.999, which SCI0 games have.
+    uses_methods_3_or_4: bool, // On views or pictures. COMP3 in SCI01, LZW1 in SCI1.
+    uses_method_2: bool, // Huffman in SCI0, COMP3 in SCI01, LZW1 in SCI1.
+    uses_dcl_methods: bool, // 8 or 18-20, which only SCI1 uses.
+    method_1_is_huffman: bool, // Rather than LZW, as it is until SCI1.
+}
+
+impl Generation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Generation::Sci0Early => "SCI0 (early)",
+            Generation::Sci0Late => "SCI0 (late)",
+            Generation::Sci01 => "SCI01",
+            Generation::Sci1Early => "SCI1 (early)",
+            Generation::Sci1Late => "SCI1 (late)",
+        }
+    }
+
+    pub fn header_size(&self) -> usize {
+        if *self == Generation::Sci1Late { 9 } else { 8 }
+    }
+
+    // What each compression method number in volume headers means.
+    pub fn compression(&self, method: usize) -> Option<Compression> {
+        match (self, method) {
+            (_, 0) => Some(Compression::None),
+            (Generation::Sci0Early | Generation::Sci0Late, 1) => Some(Compression::Lzw),
+            (Generation::Sci0Early | Generation::Sci0Late, 2) => Some(Compression::Huffman),
+            (Generation::Sci01, 1) => Some(Compression::Lzw),
+            (Generation::Sci01, 2..=4) => Some(Compression::Unsupported("COMP3")),
+            (Generation::Sci1Early | Generation::Sci1Late, 1) => Some(Compression::Huffman),
+            (Generation::Sci1Early | Generation::Sci1Late, 2..=4) => Some(Compression::Unsupported("LZW1")),
+            (Generation::Sci1Early | Generation::Sci1Late, 8 | 18..=20) => Some(Compression::Unsupported("DCL")),
+            _ => None,
+        }
+    }
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lzw => "lzw",
+            Compression::Huffman => "huffman",
+            Compression::Unsupported(name) => name,
+        }
+    }
+
+    // The number SCI0 gives it, which is what volumes are written with.
+    pub fn sci0_method(&self) -> Option<usize> {
+        match self {
+            Compression::None => Some(resource_reader::METHOD_UNCOMPRESSED),
+            Compression::Lzw => Some(resource_reader::METHOD_LZW),
+            Compression::Huffman => Some(resource_reader::METHOD_HUFFMAN),
+            Compression::Unsupported(_) => None,
+        }
+    }
+}
+
+// Late SCI1 maps start with an index of where each type's entries are, SCI0 maps are just entries.
+pub fn is_sci1_late_map(data: &[u8]) -> bool {
+    let mut previous_offset = 0;
+    for record in data.chunks_exact(3) {
+        let offset = (record[1] as usize) + ((record[2] as usize) << 8);
+        if record[0] == 0xff {
+            return offset == data.len() && previous_offset > 0
+        }
+        if record[0] & 0x80 == 0 || offset < previous_offset || offset > data.len() { return false }
+        if previous_offset > 0 && !(offset - previous_offset).is_multiple_of(6) { return false }
+        previous_offset = offset;
+    }
+    false
+}
+
+// Decides between the SCI0 layout generations while reading the map, so the entries are read the right way.
+// The headers are read directly from the source, since the entries can't be read until this is known.
+pub fn sci0_generation(entries: &[Entry], source: &dyn Source) -> Generation {
+    let mut volumes: HashMap<String, Option<Box<dyn Volume>>> = HashMap::new();
+    let mut read = |name: &str, offset: usize, length: usize| {
+        volumes.entry(name.to_string()).or_insert_with(|| source.open(name)).as_mut()?.read_at(offset, length)
+    };
+    decide(&evidence(entries, &mut read)).0
+}
+
+fn evidence(entries: &[Entry], read: &mut Reader) -> Evidence {
+    let entries: Vec<&Entry> = entries.iter().filter(|e| e.source == map::Source::Volume).collect();
+    let mut headers: Vec<(&Entry, Header)> = Vec::new();
+    for entry in entries.iter().filter(|e| e.resource_type == ResourceType::View || e.resource_type == ResourceType::Picture) {
+        let Some(header) = read(&entry.source_name(), entry.offset, 8).and_then(|h| Header::parse_sizes(&h, Generation::Sci0Late).ok()) else { continue };
+        headers.push((entry, header));
+    }
+    let methods: Vec<usize> = headers.iter().map(|(_, h)| h.method).collect();
+    let script = entries.iter().find(|e| e.resource_type == ResourceType::Script && e.resource_number == 0);
+    // Method 1 is tried both ways until a resource only decompresses one of them.
+    let method_1_is_huffman = headers.iter().filter(|(_, h)| h.method == 1).find_map(|(entry, header)| {
+        let content = read(&entry.source_name(), entry.offset + 8, header.compressed_size)?;
+        let is_lzw = resource_reader::decompress(&content, header.decompressed_size, Compression::Lzw).is_ok();
+        let is_huffman = resource_reader::decompress(&content, header.decompressed_size, Compression::Huffman).is_ok();
+        if is_lzw != is_huffman { Some(is_huffman) } else { None }
+    });
+    Evidence {
+        has_old_script_header: script.and_then(|s| read_sci0(s, read)).is_some_and(|data| has_old_script_header(&data)),
+        has_kernel_names: entries.iter().any(|e| e.resource_type == ResourceType::Vocab && e.resource_number == 999),
+        uses_methods_3_or_4: methods.iter().any(|&m| m == 3 || m == 4),
+        uses_method_2: methods.contains(&2),
+        uses_dcl_methods: methods.iter().any(|&m| m == 8 || (18..=20).contains(&m)),
+        method_1_is_huffman: method_1_is_huffman.unwrap_or(false),
+    }
+}
+
+// The generation and why, so the reason can be reported.
+fn decide(evidence: &Evidence) -> (Generation, &'static str) {
+    let uses_newer_methods = evidence.uses_methods_3_or_4 || evidence.uses_dcl_methods;
+    if evidence.has_old_script_header {
+        (Generation::Sci0Early, "Script 0 has the old header")
+    } else if evidence.has_kernel_names && !uses_newer_methods {
+        (Generation::Sci0Late, "Has kernel function names (vocab.999) as SCI0 games do, so method 2 is Huffman")
+    } else if evidence.uses_dcl_methods {
+        (Generation::Sci1Early, "Views or pictures use DCL compression (methods 8 or 18-20), which only SCI1 uses")
+    } else if evidence.method_1_is_huffman {
+        (Generation::Sci1Early, "Views or pictures with compression method 1 are Huffman rather than LZW, as in SCI1")
+    } else if evidence.uses_methods_3_or_4 {
+        (Generation::Sci01, "Views or pictures use compression methods 3 or 4, which SCI0 doesn't have, and method 1 is still LZW")
+    } else if evidence.uses_method_2 {
+        (Generation::Sci01, "Has no kernel function names (vocab.999), so method 2 is SCI01's COMP3 rather than Huffman")
+    } else {
+        (Generation::Sci0Late, "Views and pictures only use compression methods 0 and 1, and method 1 is LZW, as in SCI0")
+    }
+}
+
+// Reads a resource as SCI0 stores it, for before the map is finished.
+fn read_sci0(entry: &Entry, read: &mut Reader) -> Option<Vec<u8>> {
+    let generation = Generation::Sci0Late;
+    let header = Header::parse(&read(&entry.source_name(), entry.offset, generation.header_size())?, generation).ok()?;
+    let content = read(&entry.source_name(), entry.offset + generation.header_size(), header.compressed_size)?;
+    resource_reader::decompress(&content, header.decompressed_size, generation.compression(header.method)?).ok()
+}
+
+// The full detection, for reporting. The map has already settled the generation, and this explains it.
+pub fn detect(name: &str, map: &Map, files: &Files, map_data: &[u8]) -> Version {
+    let mut reasons: Vec<String> = Vec::new();
+    if map.generation == Generation::Sci1Late {
+        reasons.push("The map is indexed by resource type".to_string());
+    } else {
+        let mut read = |name: &str, offset: usize, length: usize| files.read_at(name, offset, length);
+        reasons.push(decide(&evidence(&map.entries, &mut read)).1.to_string());
+    }
+
+    let hash = fnv_hash(map_data);
+    let known = KNOWN_GAMES.iter().find(|(h, _, _)| *h == hash);
+    let script = map.entries.iter().find(|e| e.resource_type == ResourceType::Script && e.resource_number == 0);
+    let game_object = script.and_then(|s| resource_reader::read_first_copy(s, files).ok()).and_then(|(_, _, data)| game_object_name(&data));
+    let (title, platform) = match (known, game_object) {
+        (Some((_, title, platform)), _) => {
+            reasons.push(format!("The map's hash {:016x} is a known game", hash));
+            (title.to_string(), *platform)
+        }
+        (None, Some(game_object)) => {
+            reasons.push(format!("The map's hash {:016x} isn't a known game, so the title is the name of the game object in script 0", hash));
+            (game_object, "DOS (assumed)")
+        }
+        (None, None) => {
+            reasons.push(format!("The map's hash {:016x} isn't a known game, so the title is the folder name", hash));
+            (name.to_string(), "DOS (assumed)")
+        }
+    };
+    Version { title, generation: map.generation, platform, reasons }
+}
+
+// Script 0's first export is the game object, whose fourth selector is its name, eg SQ3. ScummVM identifies unknown games by it.
+// https://wiki.scummvm.org/index.php?title=SCI/Specifications/SCI_virtual_machine/Introduction#Script_resources
+fn game_object_name(data: &[u8]) -> Option<String> {
+    const EXPORTS: usize = 7;
+    let u16_at = |offset: usize| Some((*data.get(offset)? as usize) + ((*data.get(offset + 1)? as usize) << 8));
+    let mut offset = if has_old_script_header(data) { 2 } else { 0 };
+    loop {
+        let block_type = u16_at(offset)?;
+        let size = u16_at(offset + 2)?;
+        if block_type == 0 || size < 4 { return None }
+        if block_type == EXPORTS { break }
+        offset += size;
+    }
+    let game_object = u16_at(offset + 6)?; // After the block header and the export count.
+    let name = u16_at(game_object + 6)?;
+    let length = data.get(name..)?.iter().position(|&b| b == 0)?;
+    let name = &data[name..(name + length)];
+    if name.is_empty() || !name.iter().all(|b| b.is_ascii_graphic() || *b == b' ') { return None }
+    Some(String::from_utf8_lossy(name).to_string())
+}
+
+// Early SCI0 scripts start with a 2 byte count before their blocks, which ScummVM checks for by walking the blocks.
+fn has_old_script_header(data: &[u8]) -> bool {
+    const OBJECT_TYPES: usize = 17;
+    let mut offset = 2;
+    while offset + 2 <= data.len() {
+        let object_type = (data[offset] as usize) + ((data[offset + 1] as usize) << 8);
+        if object_type == 0 { return offset + 2 == data.len() }
+        if object_type >= OBJECT_TYPES || offset + 4 > data.len() { return false }
+        let skip = (data[offset + 2] as usize) + ((data[offset + 3] as usize) << 8);
+        if skip < 2 { return false }
+        offset += skip;
+    }
+    false
+}
+
+// FNV-1a, which is plenty for telling games apart.
+fn fnv_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_reader::tests::{sample_game, sample_resources, SAMPLE_PATH};
+    use crate::resource_source::Memory;
+    use crate::resource_writer::{self, Resource};
+
+    #[test]
+    fn it_detects_the_sample_game() {
+        let (map, files) = sample_game();
+        let map_data = std::fs::read(format!("{}/resource.map", SAMPLE_PATH)).unwrap();
+        let version = detect("NewYearsMystery", &map, &files, &map_data);
+        assert_eq!((version.title.as_str(), version.generation, version.platform), ("New Year's Mystery", Generation::Sci0Late, "DOS"));
+    }
+
+    #[test]
+    fn it_recognises_late_sci1_maps() {
+        // Views then pictures, one entry each, then the end.
+        let mut data: Vec<u8> = vec![0x80, 9, 0, 0x81, 15, 0, 0xff, 21, 0];
+        data.extend([0; 12]);
+        assert!(is_sci1_late_map(&data));
+        assert!(!is_sci1_late_map(&data[..20]));
+        assert!(!is_sci1_late_map(&std::fs::read(format!("{}/resource.map", SAMPLE_PATH)).unwrap()));
+    }
+
+    #[test]
+    fn it_recognises_old_script_headers() {
+        assert!(has_old_script_header(&[5, 0, 1, 0, 4, 0, 0, 0]));
+        assert!(!has_old_script_header(&[1, 0, 4, 0, 0, 0]));
+    }
+
+    #[test]
+    fn it_decides_the_generation_in_order() {
+        let none = Evidence {
+            has_old_script_header: false, has_kernel_names: false, uses_methods_3_or_4: false,
+            uses_method_2: false, uses_dcl_methods: false, method_1_is_huffman: false,
+        };
+        let decided = |evidence: Evidence| decide(&evidence).0;
+        assert_eq!(decided(Evidence { has_old_script_header: true, uses_methods_3_or_4: true, ..none }), Generation::Sci0Early);
+        assert_eq!(decided(Evidence { has_kernel_names: true, uses_method_2: true, ..none }), Generation::Sci0Late);
+        assert_eq!(decided(Evidence { has_kernel_names: true, uses_methods_3_or_4: true, ..none }), Generation::Sci01);
+        assert_eq!(decided(Evidence { uses_method_2: true, ..none }), Generation::Sci01);
+        assert_eq!(decided(Evidence { uses_methods_3_or_4: true, method_1_is_huffman: true, ..none }), Generation::Sci1Early);
+        assert_eq!(decided(Evidence { has_kernel_names: true, uses_dcl_methods: true, ..none }), Generation::Sci1Early);
+        assert_eq!(decided(none), Generation::Sci0Late);
+    }
+
+    #[test]
+    fn it_tells_sci1_huffman_from_sci0_lzw() {
+        // The same Huffman view, labelled with SCI0's method 2 then SCI1's method 1, in a game without vocab.999.
+        let data: Vec<u8> = b"Roger Wilco, janitor. ".repeat(20);
+        let game = |method: usize| {
+            let resource = Resource { type_number: ResourceType::View.number(), resource_number: 1, data: data.clone(), method: resource_reader::METHOD_HUFFMAN };
+            let mut packed = resource_writer::pack(&[resource], None);
+            packed.volumes[0][6] = method as u8;
+            Memory { files: vec![("resource.map".to_string(), packed.map), ("resource.001".to_string(), packed.volumes.remove(0))] }
+        };
+        assert_eq!(Map::read_from(&game(2)).generation, Generation::Sci01);
+        let game = game(1);
+        let map = Map::read_from(&game);
+        assert_eq!((map.generation, map.entries[0].generation), (Generation::Sci1Early, Generation::Sci1Early));
+        assert_eq!(resource_reader::read(&map.entries[0], &Files::new(Box::new(game))), data);
+    }
+
+    #[test]
+    fn it_reads_the_game_object_name() {
+        let (_, data) = sample_resources().into_iter().find(|(e, _)| e.resource_type == ResourceType::Script && e.resource_number == 0).unwrap();
+        assert_eq!(game_object_name(&data).as_deref(), Some("Template")); // Made with the SCI Studio template game.
+        assert_eq!(game_object_name(&[7, 0, 2, 0]), None);
+    }
+}
@@ -7,39 +7,39 @@ pub struct View {
 }
 
 impl View {
-    pub fn parse(data: &[u8]) -> View {
-        let count = (data[0] as usize) + ((data[1] as usize) << 8);
-        let mirror_flags = (data[2] as usize) + ((data[3] as usize) << 8);
+    // Every offset is checked, so corrupt data gives an error rather than a panic.
+    pub fn parse(data: &[u8]) -> Result<View, String> {
+        let count = parse_2_byte_le(data, 0)?;
+        let mirror_flags = parse_2_byte_le(data, 2)?;
         // 4-7 is unknown.
-        let mut loops: Vec<Loop> = Vec::with_capacity(count as usize);
+        let mut loops: Vec<Loop> = Vec::with_capacity(count.min(16));
         for i in 0..count {
             // Read the position.
-            let offset = 8 + (i as usize) * 2;
-            let position = (data[offset] as usize) + ((data[offset + 1] as usize) << 8);
+            let position = parse_2_byte_le(data, 8 + i * 2)?;
             // Read the loop.
-            let loop_data = &data[position..];
+            let loop_data = data.get(position..).ok_or(format!("Loop {} is past the end, at {}", i, position))?;
             let is_mirrored = (mirror_flags >> i) & 1 != 0;
-            let view_loop = Loop::parse(loop_data, data, is_mirrored);
+            let view_loop = Loop::parse(loop_data, data, is_mirrored)?;
             loops.push(view_loop);
         }
-        View{ loops }
+        Ok(View{ loops })
     }
 }
 
 pub struct Loop {
     pub cels: Vec<Cel>,
+    pub is_mirrored: bool, // The cels have already been flipped horizontally.
 }
 impl Loop {
-    fn parse(data: &[u8], resource: &[u8], is_mirrored: bool) -> Loop {
-        let count = (data[0] as usize) + ((data[1] as usize) << 8);
+    fn parse(data: &[u8], resource: &[u8], is_mirrored: bool) -> Result<Loop, String> {
+        let count = parse_2_byte_le(data, 0)?;
         // 2-3 is unknown.
-        let positions_onwards = &data[4..];
-        let positions_data = &positions_onwards[..(count*2)];
-        let positions: Vec<usize> = positions_data.chunks_exact(2).map(parse_2_byte_le).collect();
-        let cels: Vec<Cel> = positions.iter().map(|&p| {
-            Cel::parse(&resource[p..], is_mirrored)
+        let cels: Result<Vec<Cel>, String> = (0..count).map(|i| {
+            let p = parse_2_byte_le(data, 4 + i * 2)?;
+            let cel_data = resource.get(p..).ok_or(format!("Cel {} is past the end, at {}", i, p))?;
+            Cel::parse(cel_data, is_mirrored)
         }).collect();
-        Loop { cels }
+        Ok(Loop { cels: cels?, is_mirrored })
     }
 }
 
@@ -48,15 +48,22 @@ pub struct Cel {
     pub width: usize,
     pub height: usize,
     pub pixels: Vec<u8>, // EGA palette indexes.
+    pub x_placement: i8, // Where the cel is drawn relative to the actor's position.
+    pub y_placement: i8,
 }
 impl Cel {
-    fn parse(data: &[u8], is_mirrored: bool) -> Cel {
-        let width = (data[0] as usize) + ((data[1] as usize) << 8);
-        let height = (data[2] as usize) + ((data[3] as usize) << 8);
-        let _x_placement_modifier = data[4];
-        let _y_placement_modifier = data[5];
+    fn parse(data: &[u8], is_mirrored: bool) -> Result<Cel, String> {
+        if data.len() < 7 { return Err("Cel header is past the end".to_string()) }
+        let width = parse_2_byte_le(data, 0)?;
+        let height = parse_2_byte_le(data, 2)?;
+        let x_placement = data[4] as i8;
+        let y_placement = data[5] as i8;
         let transparent_color = data[6];
         let image_source_data = &data[7..];
+        // Each byte is a run of up to 15 pixels, so a corrupt size can't ask for more than the data could hold.
+        if width * height > image_source_data.len() * 15 {
+            return Err(format!("Cel of {}x{} is bigger than its data", width, height))
+        }
         let mut pixels: Vec<u8> = Vec::with_capacity(width * height);
         'outer: for b in image_source_data {
             let count = b >> 4;
@@ -75,12 +82,13 @@ impl Cel {
         if is_mirrored {
             pixels = mirror(&pixels, width);
         }
-        Cel { width, height, pixels }
+        Ok(Cel { width, height, pixels, x_placement, y_placement })
     }
 }
 
-fn mirror(pixels: &[u8], width: usize) -> Vec<u8> {
+pub fn mirror(pixels: &[u8], width: usize) -> Vec<u8> {
     let mut out: Vec<u8> = Vec::with_capacity(pixels.len());
+    if width == 0 { return out }
     for chunk in pixels.chunks_exact(width) {
         for p in chunk.iter().rev() {
             out.push(*p);
@@ -89,6 +97,9 @@ fn mirror(pixels: &[u8], width: usize) -> Vec<u8> {
     out
 }
 
-fn parse_2_byte_le(data: &[u8]) -> usize {
-    (data[0] as usize) + ((data[1] as usize) << 8)
+fn parse_2_byte_le(data: &[u8], offset: usize) -> Result<usize, String> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok((bytes[0] as usize) + ((bytes[1] as usize) << 8)),
+        None => Err(format!("Offset {} is past the end, at {}", offset, data.len())),
+    }
 }
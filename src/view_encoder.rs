@@ -0,0 +1,136 @@
+// This is responsible for building view resources, the inverse of View::parse, for fan patches that replace sprites.
+// Layout: loop count, mirror flags (bit per loop), 4 unknown bytes, then the loop offsets.
+// Each loop: cel count, 2 unknown bytes, then the cel offsets (from the start of the resource).
+// Each cel: width, height, x/y placement, transparent colour, then RLE bytes of count (high nibble) and colour (low nibble).
+// https://sciwiki.sierrahelp.com/index.php/SCI_Specifications:_Chapter_2_-_Resource_files#View_resources
+
+use crate::palette::TRANSPARENT;
+use crate::view::{self, Cel, View};
+
+// Transparent pixels are written as the given colour, which the cels mustn't otherwise use.
+pub fn encode(view: &View, transparent_colour: u8) -> Vec<u8> {
+    assert!(view.loops.len() <= 16, "Views can have at most 16 loops, as the mirror flags are 16 bits!");
+    assert!(transparent_colour < 16, "Transparent colour must be an EGA colour 0-15!");
+    let mut out: Vec<u8> = Vec::new();
+    let mirror_flags: usize = view.loops.iter().enumerate().map(|(i, l)| (l.is_mirrored as usize) << i).sum();
+    append_u16(&mut out, view.loops.len());
+    append_u16(&mut out, mirror_flags);
+    out.extend_from_slice(&[0; 4]); // Unknown.
+    let loop_offsets_position = out.len();
+    out.extend(vec![0; view.loops.len() * 2]);
+
+    // Loops are stored unmirrored, as the interpreter flips them when loading.
+    // Mirrored loops usually share the data of the loop they mirror, so identical loops are only stored once.
+    let mut stored_loops: Vec<(Vec<Vec<u8>>, usize)> = Vec::new(); // Encoded cels, and where the loop was written.
+    for (li, l) in view.loops.iter().enumerate() {
+        let cels: Vec<Vec<u8>> = l.cels.iter().map(|c| {
+            if l.is_mirrored {
+                let unmirrored = Cel { pixels: view::mirror(&c.pixels, c.width), ..c.clone() };
+                encode_cel(&unmirrored, transparent_colour)
+            } else {
+                encode_cel(c, transparent_colour)
+            }
+        }).collect();
+        let loop_offset = match stored_loops.iter().find(|(stored, _)| *stored == cels) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = out.len();
+                append_u16(&mut out, cels.len());
+                out.extend_from_slice(&[0; 2]); // Unknown.
+                let cel_offsets_position = out.len();
+                out.extend(vec![0; cels.len() * 2]);
+                for (ci, cel) in cels.iter().enumerate() {
+                    let cel_offset = out.len();
+                    set_u16(&mut out, cel_offsets_position + ci * 2, cel_offset);
+                    out.extend_from_slice(cel);
+                }
+                stored_loops.push((cels, offset));
+                offset
+            }
+        };
+        set_u16(&mut out, loop_offsets_position + li * 2, loop_offset);
+    }
+    assert!(out.len() <= 0xffff, "View is too big for 16 bit offsets!");
+    out
+}
+
+fn encode_cel(cel: &Cel, transparent_colour: u8) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    append_u16(&mut out, cel.width);
+    append_u16(&mut out, cel.height);
+    out.push(cel.x_placement as u8);
+    out.push(cel.y_placement as u8);
+    out.push(transparent_colour);
+    if cel.width == 0 || cel.height == 0 { return out } // Empty cels are just the header.
+    // Runs are kept within rows, as Sierra's tools did.
+    for row in cel.pixels[..(cel.width * cel.height)].chunks_exact(cel.width) {
+        let colours: Vec<u8> = row.iter().map(|&p| {
+            if p == TRANSPARENT { return transparent_colour }
+            assert!(p < 16, "Cel pixels must be EGA colours 0-15 or transparent!");
+            assert!(p != transparent_colour, "Cel uses colour {}, which was chosen as the transparent colour!", p);
+            p
+        }).collect();
+        let mut x = 0;
+        while x < colours.len() {
+            let colour = colours[x];
+            let count = colours[x..].iter().take(15).take_while(|&&c| c == colour).count();
+            out.push(((count as u8) << 4) | colour);
+            x += count;
+        }
+    }
+    out
+}
+
+// The first EGA colour no cel uses, which is handy as the transparent colour.
+pub fn unused_colour(view: &View) -> Option<u8> {
+    (0..16).find(|&colour| view.loops.iter().all(|l| l.cels.iter().all(|c| !c.pixels.contains(&colour))))
+}
+
+fn append_u16(out: &mut Vec<u8>, value: usize) {
+    assert!(value <= 0xffff, "Value doesn't fit in 16 bits: {}", value);
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+fn set_u16(out: &mut [u8], position: usize, value: usize) {
+    assert!(value <= 0xffff, "Value doesn't fit in 16 bits: {}", value);
+    out[position] = value as u8;
+    out[position + 1] = (value >> 8) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::resource_reader::tests::sample_resources;
+
+    #[test]
+    fn it_round_trips_sample_views() {
+        for (entry, data) in sample_resources() {
+            if entry.resource_type != map::ResourceType::View { continue }
+            let original = View::parse(&data).unwrap();
+            let encoded = encode(&original, unused_colour(&original).unwrap());
+            let decoded = View::parse(&encoded).unwrap();
+            assert_eq!(decoded.loops.len(), original.loops.len(), "View {} loop count", entry.resource_number);
+            for (a, b) in original.loops.iter().zip(&decoded.loops) {
+                assert_eq!(a.is_mirrored, b.is_mirrored);
+                assert_eq!(a.cels.len(), b.cels.len());
+                for (c, d) in a.cels.iter().zip(&b.cels) {
+                    assert_eq!((c.width, c.height, c.x_placement, c.y_placement), (d.width, d.height, d.x_placement, d.y_placement));
+                    assert!(c.pixels == d.pixels, "View {} pixels differ", entry.resource_number);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_encodes_empty_cels_as_just_a_header() {
+        let empty = |width, height| Cel { width, height, pixels: vec![], x_placement: 1, y_placement: 2 };
+        let view = View { loops: vec![view::Loop { cels: vec![empty(0, 3), empty(4, 0)], is_mirrored: false }] };
+        let encoded = encode(&view, 0);
+        assert_eq!(encoded.len(), 8 + 2 + 4 + 2 * 2 + 7 * 2);
+        let decoded = View::parse(&encoded).unwrap();
+        let sizes: Vec<(usize, usize, usize)> = decoded.loops[0].cels.iter().map(|c| (c.width, c.height, c.pixels.len())).collect();
+        assert_eq!(sizes, [(0, 3, 0), (4, 0, 0)]);
+    }
+}
@@ -9,6 +9,7 @@ const LUMINANCE_WEIGHT: f32             = 1.0;
 const EQUAL_COLOR_TOLERANCE: f32        = 30.0;
 const DOMINANT_DIRECTION_THRESHOLD: f32 = 3.6;
 const STEEP_DIRECTION_THRESHOLD: f32    = 2.2;
+const MIN_BAND_HEIGHT: usize            = 16; // Rows, so small cels aren't split into bands that take longer to start than to scale.
 
 // BlendType must fit into the value range of 2 bit!!!
 // I'm using constants instead of an enum here because enums don't implement Copy which causes issues if you want to reuse them.
@@ -139,11 +140,11 @@ macro_rules! set_bottom_l { ($b:expr, $bt:expr) => { $b |= $bt << 6 } }
 
 // Buffer is assumed to be initialized before preprocessing!
 #[inline]
-fn get_top_r(b: u8)    -> BlendType { return unsafe { ::std::mem::transmute(0x3 & (b >> 2)) }; }
+fn get_top_r(b: u8)    -> BlendType { return 0x3 & (b >> 2); }
 #[inline]
-fn get_bottom_r(b: u8) -> BlendType { return unsafe { ::std::mem::transmute(0x3 & (b >> 4)) }; }
+fn get_bottom_r(b: u8) -> BlendType { return 0x3 & (b >> 4); }
 #[inline]
-fn get_bottom_l(b: u8) -> BlendType { return unsafe { ::std::mem::transmute(0x3 & (b >> 6)) }; }
+fn get_bottom_l(b: u8) -> BlendType { return 0x3 & (b >> 6); }
 
 // TODO replace these rotations with macros like in the C++ version?
 impl Kernel3x3 {
@@ -196,15 +197,22 @@ impl RotatableBlendInfo for u8 {
 }
 
 // pitch_elements is number of u32 elements, eg pixels, not bytes.
-fn fill_block(trg: *mut u32, pitch_elements: isize, col: u32, block_width: isize, block_height: isize) {
-    let mut offset: isize = 0;
-    let next_line_offset: isize = pitch_elements - block_width;
-    for _ in 0..block_height {
-        for _ in 0..block_width {
-            unsafe { *trg.offset(offset) = col; }
-            offset += 1;
-        }
-        offset += next_line_offset;
+// Each scale has its own fill, as copying a fixed size row is much faster than filling an unknown length.
+fn fill_block(trg: &mut [u32], pitch_elements: usize, col: u32, block_width: usize, block_height: usize) {
+    match block_width {
+        2 => fill_rows::<2>(trg, pitch_elements, col, block_height),
+        3 => fill_rows::<3>(trg, pitch_elements, col, block_height),
+        4 => fill_rows::<4>(trg, pitch_elements, col, block_height),
+        5 => fill_rows::<5>(trg, pitch_elements, col, block_height),
+        6 => fill_rows::<6>(trg, pitch_elements, col, block_height),
+        _ => panic!("Invalid scale"),
+    }
+}
+
+fn fill_rows<const N: usize>(trg: &mut [u32], pitch_elements: usize, col: u32, rows: usize) {
+    for row in 0..rows {
+        let start = row * pitch_elements;
+        trg[start..(start + N)].copy_from_slice(&[col; N]);
     }
 }
 
@@ -234,11 +242,11 @@ fn alpha_grad(m: i32, n: i32, pix_back: &mut u32, pix_front: u32) {
 
 trait Scaler {
     fn scale(&self) -> u8;
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix);
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix);
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix);
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix);
-    fn blend_corner(&self, col: u32, out: &OutputMatrix);
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix);
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix);
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix);
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix);
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix);
 }
 
 struct Scaler2x {}
@@ -252,27 +260,27 @@ impl Scaler for Scaler2x {
         return 2;
     }
 
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(2 - 1, 0), col);
         alpha_grad(3, 4, out.pixel_ref(2 - 1, 1), col);
     }
 
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 2-1), col);
         alpha_grad(3, 4, out.pixel_ref(1, 2-1), col);
     }
 
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(1, 0), col);
         alpha_grad(1, 4, out.pixel_ref(0, 1), col);
         alpha_grad(5, 6, out.pixel_ref(1, 1), col); //[!] fixes 7/8 used in xBR
     }
 
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 2, out.pixel_ref(1, 1), col);
     }
 
-    fn blend_corner(&self, col: u32, out: &OutputMatrix) {
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(21, 100, out.pixel_ref(1, 1), col); //exact: 1 - pi/4 = 0.2146018366
     }
 }
@@ -282,7 +290,7 @@ impl Scaler for Scaler3x {
         return 3;
     }
 
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(3 - 1, 0), col);
         alpha_grad(1, 4, out.pixel_ref(3 - 2, 2), col);
 
@@ -290,7 +298,7 @@ impl Scaler for Scaler3x {
         *out.pixel_ref(3 - 1, 2) = col;
     }
 
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 3 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 3 - 2), col);
 
@@ -298,7 +306,7 @@ impl Scaler for Scaler3x {
         *out.pixel_ref(2, 3 - 1) = col;
     }
 
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(2, 0), col);
         alpha_grad(1, 4, out.pixel_ref(0, 2), col);
         alpha_grad(3, 4, out.pixel_ref(2, 1), col);
@@ -306,13 +314,13 @@ impl Scaler for Scaler3x {
         *out.pixel_ref(2, 2) = col;
     }
 
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 8, out.pixel_ref(1, 2), col); //conflict with other rotations for this odd scale
         alpha_grad(1, 8, out.pixel_ref(2, 1), col);
         alpha_grad(7, 8, out.pixel_ref(2, 2), col); //
     }
 
-    fn blend_corner(&self, col: u32, out: &OutputMatrix) {
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(45, 100, out.pixel_ref(2, 2), col); //exact: 0.4545939598
     }
 }
@@ -322,7 +330,7 @@ impl Scaler for Scaler4x {
         return 4;
     }
 
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(4 - 1, 0), col);
         alpha_grad(1, 4, out.pixel_ref(4 - 2, 2), col);
 
@@ -333,7 +341,7 @@ impl Scaler for Scaler4x {
         *out.pixel_ref(4 - 1, 3) = col;
     }
 
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 4 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 4 - 2), col);
 
@@ -344,7 +352,7 @@ impl Scaler for Scaler4x {
         *out.pixel_ref(3, 4 - 1) = col;
     }
 
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(3, 4, out.pixel_ref(3, 1), col);
         alpha_grad(3, 4, out.pixel_ref(1, 3), col);
         alpha_grad(1, 4, out.pixel_ref(3, 0), col);
@@ -357,13 +365,13 @@ impl Scaler for Scaler4x {
         *out.pixel_ref(2, 3) = col;
     }
 
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 2, out.pixel_ref(4 - 1, 4 / 2    ), col);
         alpha_grad(1, 2, out.pixel_ref(4 - 2, 4 / 2 + 1), col);
         *out.pixel_ref(4 - 1, 4 - 1) = col;
     }
 
-    fn blend_corner(&self, col: u32, out: &OutputMatrix) {
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(68, 100, out.pixel_ref(3, 3), col); //exact: 0.6848532563
         alpha_grad( 9, 100, out.pixel_ref(3, 2), col); //0.08677704501
         alpha_grad( 9, 100, out.pixel_ref(2, 3), col); //0.08677704501
@@ -375,7 +383,7 @@ impl Scaler for Scaler5x {
         return 5;
     }
 
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(5 - 1, 0), col);
         alpha_grad(1, 4, out.pixel_ref(5 - 2, 2), col);
         alpha_grad(1, 4, out.pixel_ref(5 - 3, 4), col);
@@ -389,7 +397,7 @@ impl Scaler for Scaler5x {
         *out.pixel_ref(5 - 2, 4) = col;
     }
 
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 5 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 5 - 2), col);
         alpha_grad(1, 4, out.pixel_ref(4, 5 - 3), col);
@@ -403,7 +411,7 @@ impl Scaler for Scaler5x {
         *out.pixel_ref(4, 5 - 2) = col;
     }
 
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 5 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 5 - 2), col);
         alpha_grad(3, 4, out.pixel_ref(1, 5 - 1), col);
@@ -422,7 +430,7 @@ impl Scaler for Scaler5x {
         *out.pixel_ref(5 - 1, 3) = col;
     }
 
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 8, out.pixel_ref(5 - 1, 5 / 2    ), col); //conflict with other rotations for this odd scale
         alpha_grad(1, 8, out.pixel_ref(5 - 2, 5 / 2 + 1), col);
         alpha_grad(1, 8, out.pixel_ref(5 - 3, 5 / 2 + 2), col); //
@@ -433,7 +441,7 @@ impl Scaler for Scaler5x {
         *out.pixel_ref(4, 4) = col;
     }
 
-    fn blend_corner(&self, col: u32, out: &OutputMatrix) {
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(86, 100, out.pixel_ref(4, 4), col); //exact: 0.8631434088
         alpha_grad(23, 100, out.pixel_ref(4, 3), col); //0.2306749731
         alpha_grad(23, 100, out.pixel_ref(3, 4), col); //0.2306749731
@@ -445,7 +453,7 @@ impl Scaler for Scaler6x {
         return 6;
     }
 
-    fn blend_line_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(6 - 1, 0), col);
         alpha_grad(1, 4, out.pixel_ref(6 - 2, 2), col);
         alpha_grad(1, 4, out.pixel_ref(6 - 3, 4), col);
@@ -463,7 +471,7 @@ impl Scaler for Scaler6x {
         *out.pixel_ref(6 - 2, 5) = col;
     }
 
-    fn blend_line_steep(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 6 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 6 - 2), col);
         alpha_grad(1, 4, out.pixel_ref(4, 6 - 3), col);
@@ -481,7 +489,7 @@ impl Scaler for Scaler6x {
         *out.pixel_ref(5, 6 - 2) = col;
     }
 
-    fn blend_line_steep_and_shallow(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_steep_and_shallow(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 4, out.pixel_ref(0, 6 - 1), col);
         alpha_grad(1, 4, out.pixel_ref(2, 6 - 2), col);
         alpha_grad(3, 4, out.pixel_ref(1, 6 - 1), col);
@@ -504,7 +512,7 @@ impl Scaler for Scaler6x {
         *out.pixel_ref(6 - 1, 3) = col;
     }
 
-    fn blend_line_diagonal(&self, col: u32, out: &OutputMatrix) {
+    fn blend_line_diagonal(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(1, 2, out.pixel_ref(6 - 1, 6 / 2    ), col);
         alpha_grad(1, 2, out.pixel_ref(6 - 2, 6 / 2 + 1), col);
         alpha_grad(1, 2, out.pixel_ref(6 - 3, 6 / 2 + 2), col);
@@ -514,7 +522,7 @@ impl Scaler for Scaler6x {
         *out.pixel_ref(6 - 1, 6 - 2) = col;
     }
 
-    fn blend_corner(&self, col: u32, out: &OutputMatrix) {
+    fn blend_corner(&self, col: u32, out: &mut OutputMatrix) {
         alpha_grad(97, 100, out.pixel_ref(5, 5), col); //exact: 0.9711013910
         alpha_grad(42, 100, out.pixel_ref(4, 5), col); //0.4236372243
         alpha_grad(42, 100, out.pixel_ref(5, 4), col); //0.4236372243
@@ -524,18 +532,19 @@ impl Scaler for Scaler6x {
 }
 
 // Scale, degree template <size_t N, RotationDegree rot_deg>
-struct OutputMatrix {
+// The output starts at the top left of the pixel's block, and runs to the end of the band.
+struct OutputMatrix<'a> {
     scale: u8,
     rot_deg: RotationDegree,
-    out: *mut u32,
+    out: &'a mut [u32],
     out_width: i32,
 }
 
-impl OutputMatrix {
-    fn pixel_ref(&self, i: i32, j: i32) -> &mut u32 {
+impl OutputMatrix<'_> {
+    fn pixel_ref(&mut self, i: i32, j: i32) -> &mut u32 {
         let i_old = rotation_i_old(&self.rot_deg, i, j, self.scale);
         let j_old = rotation_j_old(&self.rot_deg, i, j, self.scale);
-        return unsafe { self.out.offset((j_old + i_old * self.out_width) as isize).as_mut().unwrap() }
+        return &mut self.out[(j_old + i_old * self.out_width) as usize]
     } 
 }
 
@@ -560,6 +569,8 @@ fn rotation_j_old(rot: &RotationDegree, i: i32, j: i32, n: u8) -> i32 {
 }
 
 /// result: F, G, J, K corners of "GradientType"
+/// Always inlined, otherwise being called for the first row of bands stops it being inlined in the main loop, which is much slower.
+#[inline(always)]
 fn pre_process_corners(ker: &Kernel4x4) -> BlendResult {
     if (ker.f == ker.g && ker.j == ker.k) || (ker.f == ker.j && ker.g == ker.k) {
         return BlendResult { blend_f: BLEND_TYPE_NONE, blend_g: BLEND_TYPE_NONE, blend_j: BLEND_TYPE_NONE, blend_k: BLEND_TYPE_NONE };
@@ -614,7 +625,7 @@ fn blend_pixel(scale: u8,
                 scaler: &Box<dyn Scaler>,
                 rot_deg: RotationDegree,
                 ker: &Kernel3x3,
-                target: *mut u32,
+                target: &mut [u32],
                 trg_width: u32,
                 blend: u8) { //result of preprocessing all four corners of pixel "e"
 
@@ -646,7 +657,7 @@ fn blend_pixel(scale: u8,
 
         let px: u32 = if dist(ker.e, ker.f) <= dist(ker.e, ker.h) { ker.f } else { ker.h }; //choose most similar color
 
-        let out = &OutputMatrix { scale: scale, rot_deg: rot_deg, out: target, out_width: trg_width as i32 };
+        let out = &mut OutputMatrix { scale: scale, rot_deg: rot_deg, out: target, out_width: trg_width as i32 };
 
         if do_line_blend {
             let fg: f32 = dist(ker.f, ker.g); //test sample: 70% of values cmp:max(fg, hc) / cmp::min(fg, hc) are between 1.1 and 3.7 with median being 1.9
@@ -674,21 +685,16 @@ fn blend_pixel(scale: u8,
     }
 }
 
-fn do_scale(scale: u8, src: &[u32], trg: *mut u32, src_width: i32, src_height: i32) {
-    let y_first: i32 = 0;
-    let y_last = src_height;
+// Scales the source rows y_first..y_last into trg, which is just the output rows for them, so bands can be done in parallel.
+fn do_scale(scale: u8, src: &[u32], trg: &mut [u32], src_width: i32, src_height: i32, y_first: i32, y_last: i32) {
     if y_first >= y_last { return }
     if src_width <= 0 { return }
 
     let trg_width = src_width * scale as i32;
 
-    //"use" space at the end of the image as temporary buffer for "on the fly preprocessing": we even could use larger area of
-    //"sizeof(uint32_t) * src_width * (y_last - y_first)" bytes without risk of accidental overwriting before accessing
+    //buffer for "on the fly preprocessing", each band has its own so they don't race
     let buffer_size = src_width;
-    let pre_proc_buffer_ptr: *mut u8 = unsafe { (trg.offset((y_last * scale as i32 * trg_width) as isize) as *mut u8).offset(-(buffer_size as isize)) };
-    unsafe { pre_proc_buffer_ptr.write_bytes(0, buffer_size as usize) };
-    let pre_proc_buffer: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(pre_proc_buffer_ptr, buffer_size as usize) };
-    // let mut pre_proc_buffer: Vec<u8> = vec![0; buffer_size as usize];
+    let mut pre_proc_buffer: Vec<u8> = vec![0; buffer_size as usize];
 
     let scaler = select_scaler(scale);
 
@@ -746,7 +752,7 @@ fn do_scale(scale: u8, src: &[u32], trg: *mut u32, src_width: i32, src_height: i
     }
     //------------------------------------------------------------------------------------
     for y in y_first..y_last {
-        let mut out: *mut u32 = unsafe { trg.offset((scale as i32 * y * trg_width) as isize) };
+        let mut out = (scale as i32 * (y - y_first) * trg_width) as usize; //start of the current block in trg
 
         let s_m1 = &src[((src_width * cmp::max(y - 1, 0)) as usize)..];
         let s_0  = &src[((src_width * y) as usize)..]; //center line
@@ -807,7 +813,7 @@ fn do_scale(scale: u8, src: &[u32], trg: *mut u32, src_width: i32, src_height: i
             }
 
             //fill block of size scale * scale with the given color            
-            fill_block(out, trg_width as isize, ker4.f, scale as isize, scale as isize);
+            fill_block(&mut trg[out..], trg_width as usize, ker4.f, scale as usize, scale as usize);
             //place *after* preprocessing step, to not overwrite the results while processing the the last pixel!
 
             //blend four corners of current pixel
@@ -825,27 +831,100 @@ fn do_scale(scale: u8, src: &[u32], trg: *mut u32, src_width: i32, src_height: i
                     h: ker4.j,
                     i: ker4.k };
 
-                blend_pixel(scale, &scaler, RotationDegree::Rot0,   &ker3,          out, trg_width as u32, blend_xy);
-                blend_pixel(scale, &scaler, RotationDegree::Rot90,  &ker3.rot90(),  out, trg_width as u32, blend_xy.blend_info_rot90());
-                blend_pixel(scale, &scaler, RotationDegree::Rot180, &ker3.rot180(), out, trg_width as u32, blend_xy.blend_info_rot180());
-                blend_pixel(scale, &scaler, RotationDegree::Rot270, &ker3.rot270(), out, trg_width as u32, blend_xy.blend_info_rot270());
+                blend_pixel(scale, &scaler, RotationDegree::Rot0,   &ker3,          &mut trg[out..], trg_width as u32, blend_xy);
+                blend_pixel(scale, &scaler, RotationDegree::Rot90,  &ker3.rot90(),  &mut trg[out..], trg_width as u32, blend_xy.blend_info_rot90());
+                blend_pixel(scale, &scaler, RotationDegree::Rot180, &ker3.rot180(), &mut trg[out..], trg_width as u32, blend_xy.blend_info_rot180());
+                blend_pixel(scale, &scaler, RotationDegree::Rot270, &ker3.rot270(), &mut trg[out..], trg_width as u32, blend_xy.blend_info_rot270());
             }
 
-            unsafe { out = out.offset(scale as isize); }
+            out += scale as usize;
         }
     }
 }
 
-pub fn scale(factor: u8, src: &[u32], src_width: u32, src_height: u32) -> Vec<u32> {
+// Up to the given number of threads scale bands at once. Callers already running on a pool of threads should pass 1.
+pub fn scale(factor: u8, src: &[u32], src_width: u32, src_height: u32, threads: usize) -> Vec<u32> {
+    let bands = threads.min(src_height as usize / MIN_BAND_HEIGHT).max(1);
+    scale_in_bands(factor, src, src_width, src_height, bands)
+}
+
+// Splits the image into bands of rows, each scaled on its own thread. xBRZ looks at the rows either side of a band,
+// so the result is the same however many bands there are.
+pub fn scale_in_bands(factor: u8, src: &[u32], src_width: u32, src_height: u32, bands: usize) -> Vec<u32> {
     if factor == 1 {
         return Vec::from(src);
     }
 
     let mut output: Vec<u32> = vec![0; (src_width * src_height * factor as u32 * factor as u32) as usize];
-    do_scale(factor, src, output.as_mut_ptr(), src_width as i32, src_height as i32);
+    let band_height = (src_height as usize).div_ceil(bands.max(1)).max(1);
+    let band_size = band_height * (src_width * factor as u32 * factor as u32) as usize;
+    if band_size == 0 { return output }
+    if band_height >= src_height as usize {
+        do_scale(factor, src, &mut output, src_width as i32, src_height as i32, 0, src_height as i32);
+        return output;
+    }
+    std::thread::scope(|scope| {
+        for (band, trg) in output.chunks_mut(band_size).enumerate() {
+            let y_first = (band * band_height) as i32;
+            let y_last = (y_first + band_height as i32).min(src_height as i32);
+            scope.spawn(move || do_scale(factor, src, trg, src_width as i32, src_height as i32, y_first, y_last));
+        }
+    });
     return output;
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map;
+    use crate::parallel;
+    use crate::picture;
+    use crate::renderer;
+    use crate::resource_reader::tests::sample_resources;
+
+    // The sample game's pictures as they're scaled when rendering, with dithering as separate pixels.
+    fn sample_pictures() -> Vec<Vec<u32>> {
+        sample_resources().into_iter().filter(|(e, _)| e.resource_type == map::ResourceType::Picture).map(|(_, data)| {
+            let picture = picture::Picture::parse(&data).unwrap();
+            picture.picture.iter().map(|&p| renderer::rgba_from_indexed_colour(p, true)).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn it_scales_the_same_in_bands() {
+        // A corner of a picture, to keep it quick without optimisations.
+        let picture = &sample_pictures()[0];
+        let (width, height) = (64, 37);
+        let src: Vec<u32> = (0..height).flat_map(|y| &picture[(y * picture::WIDTH + 100)..][..width]).copied().collect();
+        for factor in 2..=6 {
+            let whole = scale_in_bands(factor, &src, width as u32, height as u32, 1);
+            for bands in [2, 3, 8, 37, 100] {
+                assert!(scale_in_bands(factor, &src, width as u32, height as u32, bands) == whole, "{}x in {} bands differs", factor, bands);
+            }
+        }
+    }
+
+    // Run with: cargo test --release bench_ -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_full_pictures() {
+        let pictures = sample_pictures();
+        let threads = parallel::default_threads();
+        // The best of a few rounds, since other processes get in the way:
+        let time = |bands: usize| (0..5).map(|_| {
+            let start = std::time::Instant::now();
+            for src in &pictures {
+                scale_in_bands(6, src, picture::WIDTH as u32, picture::HEIGHT as u32, bands);
+            }
+            start.elapsed() / pictures.len() as u32
+        }).min().unwrap();
+        let serial = time(1);
+        let banded = time(threads);
+        println!("6x scaling of {} 320x190 pictures, per picture: 1 band {:?}, {} bands {:?}, {:.1}x speedup",
+            pictures.len(), serial, threads, banded, serial.as_secs_f64() / banded.as_secs_f64());
+    }
+}
+
 // fn u8_to_u32_slice(original: &[u8]) -> &[u32] {
 //     let count = original.len() / mem::size_of::<u32>();
 //     let ptr = original.as_ptr() as *const u32;
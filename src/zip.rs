@@ -0,0 +1,164 @@
+// This is responsible for reading files out of zip archives, so archived games don't need unpacking first.
+// Only stored and deflated files are supported, which is what zip tools use in practice, and not zip64,
+// which is only needed for archives far bigger than any floppy game.
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+use crate::inflate;
+use crate::resource_source::Volume;
+
+const END_SIGNATURE: usize = 0x06054b50;
+const CENTRAL_SIGNATURE: usize = 0x02014b50;
+const LOCAL_SIGNATURE: usize = 0x04034b50;
+const END_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = 0xffff;
+pub const METHOD_STORED: usize = 0;
+pub const METHOD_DEFLATED: usize = 8;
+
+pub struct Entry {
+    pub name: String, // Including any folders, eg disk1/RESOURCE.001.
+    pub method: usize,
+    pub compressed_size: usize,
+    pub size: usize,
+    header_offset: usize,
+}
+
+// Reads the central directory, at the end of the archive.
+pub fn entries(archive: &mut dyn Volume) -> Vec<Entry> {
+    // Find the end record, which may be followed by a comment:
+    let archive_size = archive.size();
+    let tail_size = archive_size.min(END_SIZE + MAX_COMMENT_SIZE);
+    let tail = archive.read_at(archive_size - tail_size, tail_size).expect("Zip archive is truncated!");
+    let end = (0..=(tail_size.saturating_sub(END_SIZE))).rev()
+        .find(|&i| u32_at(&tail, i) == END_SIGNATURE)
+        .expect("Not a zip archive!");
+    let entry_count = u16_at(&tail, end + 10);
+    let directory_size = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+
+    let directory = archive.read_at(directory_offset, directory_size).expect("Zip archive is truncated!");
+    let mut entries: Vec<Entry> = Vec::with_capacity(entry_count);
+    let mut position = 0;
+    for _ in 0..entry_count {
+        assert!(u32_at(&directory, position) == CENTRAL_SIGNATURE, "Zip central directory is corrupt!");
+        let flags = u16_at(&directory, position + 8);
+        assert!(flags & 1 == 0, "Encrypted zips aren't supported!");
+        let name_length = u16_at(&directory, position + 28);
+        let extra_length = u16_at(&directory, position + 30);
+        let comment_length = u16_at(&directory, position + 32);
+        let name = &directory[(position + 46)..(position + 46 + name_length)];
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: u16_at(&directory, position + 10),
+            compressed_size: u32_at(&directory, position + 20),
+            size: u32_at(&directory, position + 24),
+            header_offset: u32_at(&directory, position + 42),
+        });
+        position += 46 + name_length + extra_length + comment_length;
+    }
+    entries
+}
+
+// Where the entry's bytes start, after its local header, which can have different extra fields to the central one.
+pub fn data_offset(archive: &mut dyn Volume, entry: &Entry) -> Result<usize, String> {
+    let header = archive.read_at(entry.header_offset, 30).ok_or("Zip archive is truncated")?;
+    if u32_at(&header, 0) != LOCAL_SIGNATURE { return Err(format!("Zip local header is corrupt for: {}", entry.name)) }
+    Ok(entry.header_offset + 30 + u16_at(&header, 26) + u16_at(&header, 28))
+}
+
+// A corrupt entry gives an error rather than panicking, as it's only one file of the game.
+pub fn read(archive: &mut dyn Volume, entry: &Entry) -> Result<Vec<u8>, String> {
+    let offset = data_offset(archive, entry)?;
+    let data = archive.read_at(offset, entry.compressed_size).ok_or("Zip archive is truncated")?;
+    let data = match entry.method {
+        METHOD_STORED => data,
+        METHOD_DEFLATED => inflate::inflate(&data).map_err(|e| format!("{} in: {}", e, entry.name))?,
+        method => return Err(format!("Unsupported zip compression method {} for: {}", method, entry.name)),
+    };
+    if data.len() != entry.size { return Err(format!("Zip entry is the wrong size: {}", entry.name)) }
+    Ok(data)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    (data[offset] as usize) + ((data[offset + 1] as usize) << 8)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> usize {
+    u16_at(data, offset) + (u16_at(data, offset + 2) << 16)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // Writes a zip by hand, with a zero CRC since it isn't checked.
+    pub fn zip_data(files: &[(&str, usize, &[u8], usize)]) -> Vec<u8> {
+        let append_u16 = |out: &mut Vec<u8>, value: usize| out.extend([value as u8, (value >> 8) as u8]);
+        let append_u32 = |out: &mut Vec<u8>, value: usize| out.extend((value as u32).to_le_bytes());
+        let mut archive: Vec<u8> = Vec::new();
+        let mut directory: Vec<u8> = Vec::new();
+        for (name, method, data, size) in files {
+            let header_offset = archive.len();
+            append_u32(&mut archive, LOCAL_SIGNATURE);
+            archive.extend([20, 0, 0, 0]); // Version, flags.
+            append_u16(&mut archive, *method);
+            archive.extend([0; 8]); // Time, date, CRC.
+            append_u32(&mut archive, data.len());
+            append_u32(&mut archive, *size);
+            append_u16(&mut archive, name.len());
+            append_u16(&mut archive, 3); // Extra field, to check it's skipped.
+            archive.extend(name.as_bytes());
+            archive.extend([0; 3]);
+            archive.extend(*data);
+
+            append_u32(&mut directory, CENTRAL_SIGNATURE);
+            directory.extend([20, 0, 20, 0, 0, 0]); // Versions, flags.
+            append_u16(&mut directory, *method);
+            directory.extend([0; 8]); // Time, date, CRC.
+            append_u32(&mut directory, data.len());
+            append_u32(&mut directory, *size);
+            append_u16(&mut directory, name.len());
+            directory.extend([0; 12]); // Extra and comment lengths, disk, attributes.
+            append_u32(&mut directory, header_offset);
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = archive.len();
+        archive.extend(&directory);
+        append_u32(&mut archive, END_SIGNATURE);
+        archive.extend([0; 4]); // Disks.
+        append_u16(&mut archive, files.len());
+        append_u16(&mut archive, files.len());
+        append_u32(&mut archive, directory.len());
+        append_u32(&mut archive, directory_offset);
+        append_u16(&mut archive, 2);
+        archive.extend(b"hi"); // Comment, to check the end record is still found.
+        archive
+    }
+
+    #[test]
+    fn it_reads_stored_and_deflated_files() {
+        // "abcabcabc" as raw deflate, made by zlib.
+        let deflated: Vec<u8> = vec![0x4b, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00];
+        let mut archive = zip_data(&[
+            ("GAME/", METHOD_STORED, b"", 0),
+            ("GAME/RESOURCE.MAP", METHOD_STORED, b"stored", 6),
+            ("GAME/RESOURCE.001", METHOD_DEFLATED, &deflated, 9),
+        ]);
+        let entries = entries(&mut archive);
+        assert_eq!(entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["GAME/", "GAME/RESOURCE.MAP", "GAME/RESOURCE.001"]);
+        assert_eq!(read(&mut archive, &entries[1]).unwrap(), b"stored");
+        assert_eq!(read(&mut archive, &entries[2]).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn it_rejects_corrupt_files() {
+        let mut archive = zip_data(&[
+            ("RESOURCE.001", METHOD_DEFLATED, &[0x4b, 0x4c, 0x4a], 9), // Cut short.
+            ("RESOURCE.002", METHOD_STORED, b"short", 6),
+            ("RESOURCE.003", 12, b"bzip2", 5),
+        ]);
+        let entries = entries(&mut archive);
+        assert_eq!(read(&mut archive, &entries[0]), Err("Deflate stream ends early in: RESOURCE.001".to_string()));
+        assert_eq!(read(&mut archive, &entries[1]), Err("Zip entry is the wrong size: RESOURCE.002".to_string()));
+        assert!(read(&mut archive, &entries[2]).is_err());
+    }
+}